@@ -1,12 +1,13 @@
+use chrono::Utc;
 use rand::{distributions::Alphanumeric, Rng};
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::Arc};
 use teloxide::dispatching::UpdateHandler;
-use teloxide::types::ParseMode;
+use teloxide::types::{InputFile, ParseMode};
 use teloxide::{prelude::*, utils::command::BotCommands};
 
 use crate::riddles::{self, ChatData};
-use crate::utils::{escape_chars, send_message, Error, HandlerResult};
-use crate::{commands, models_raw, state_machine};
+use crate::utils::{download_document, escape_chars, send_message, Error, HandlerResult};
+use crate::{commands, graph, lints, models_raw, playthroughs, race, solver, state_machine};
 
 #[derive(BotCommands, Clone)]
 #[command(
@@ -22,6 +23,40 @@ enum AdminCommand {
     RemoveRiddle,
     #[command(description = "list all riddles.")]
     ListRiddles,
+    #[command(description = "export a riddle's definition as JSON.")]
+    ExportRiddle,
+    #[command(description = "create a riddle from an exported definition.")]
+    ImportRiddle,
+    #[command(description = "draw a riddle's state machine as a Graphviz or Mermaid diagram.")]
+    GraphRiddle,
+    #[command(description = "change a riddle's name, description or state machine.")]
+    EditRiddle,
+    #[command(description = "list the versions of a riddle.")]
+    RiddleVersions,
+    #[command(description = "compare two versions of a riddle.")]
+    DiffRiddle,
+    #[command(description = "make an older version of a riddle the current one.")]
+    RollbackRiddle,
+    #[command(description = "make a draft riddle playable for everyone.")]
+    PublishRiddle,
+    #[command(description = "turn a riddle back into a draft.")]
+    UnpublishRiddle,
+    #[command(
+        description = "list a riddle in the public catalog with tags, a difficulty and a blurb."
+    )]
+    CatalogRiddle,
+    #[command(description = "remove a riddle from the public catalog.")]
+    UncatalogRiddle,
+    #[command(description = "check a riddle's state machine for likely mistakes.")]
+    LintRiddle,
+    #[command(description = "find the shortest way to solve a riddle.")]
+    SolveRiddle,
+    #[command(description = "show how players are doing in a riddle.")]
+    RiddleStats,
+    #[command(description = "start a race between group chats on a riddle.")]
+    StartRace,
+    #[command(description = "end a race early and post its final standings.")]
+    EndRace,
 }
 
 #[derive(Clone, Debug)]
@@ -51,15 +86,140 @@ enum RemoveRiddleState {
     Code,
 }
 
+#[derive(Clone, Debug)]
+enum ExportRiddleState {
+    Code,
+}
+
+#[derive(Clone, Debug)]
+enum GraphRiddleState {
+    Code,
+    Format { code: String },
+}
+
+#[derive(Clone, Debug)]
+enum RiddleField {
+    Name,
+    Description,
+    StateMachine,
+}
+
+#[derive(Clone, Debug)]
+enum EditRiddleState {
+    Code,
+    Field { code: String },
+    Value { code: String, field: RiddleField },
+    // players are in states that the new version doesn't define
+    Remap { code: String, version: usize },
+}
+
+#[derive(Clone, Debug)]
+enum RiddleVersionsState {
+    Code,
+}
+
+#[derive(Clone, Debug)]
+enum DiffRiddleState {
+    Code,
+    Versions { code: String },
+}
+
+#[derive(Clone, Debug)]
+enum RollbackRiddleState {
+    Code,
+    Version { code: String },
+}
+
+#[derive(Clone, Debug)]
+enum PublishRiddleState {
+    Code { published: bool },
+}
+
+#[derive(Clone, Debug)]
+enum CatalogRiddleState {
+    Code,
+    Tags {
+        code: String,
+    },
+    Difficulty {
+        code: String,
+        tags: Vec<String>,
+    },
+    Blurb {
+        code: String,
+        tags: Vec<String>,
+        difficulty: riddles::Difficulty,
+    },
+}
+
+#[derive(Clone, Debug)]
+enum UncatalogRiddleState {
+    Code,
+}
+
+#[derive(Clone, Debug)]
+enum LintRiddleState {
+    Code,
+}
+
+#[derive(Clone, Debug)]
+enum SolveRiddleState {
+    Code,
+}
+
+#[derive(Clone, Debug)]
+enum RiddleStatsState {
+    Code,
+}
+
+#[derive(Clone, Debug)]
+enum StartRaceState {
+    Code,
+    Teams {
+        code: String,
+    },
+    Announcements {
+        code: String,
+        teams: Vec<(ChatId, String)>,
+    },
+}
+
+#[derive(Clone, Debug)]
+enum EndRaceState {
+    Code,
+}
+
+#[derive(Clone, Debug)]
+enum ImportRiddleState {
+    Code,
+    Definition { code: Option<String> },
+}
+
 #[derive(Clone, Default, Debug)]
 enum DialogueState {
     #[default]
     None,
     NewRiddle(NewRiddleState),
     RemoveRiddle(RemoveRiddleState),
+    ExportRiddle(ExportRiddleState),
+    ImportRiddle(ImportRiddleState),
+    GraphRiddle(GraphRiddleState),
+    EditRiddle(EditRiddleState),
+    RiddleVersions(RiddleVersionsState),
+    DiffRiddle(DiffRiddleState),
+    RollbackRiddle(RollbackRiddleState),
+    PublishRiddle(PublishRiddleState),
+    CatalogRiddle(CatalogRiddleState),
+    UncatalogRiddle(UncatalogRiddleState),
+    LintRiddle(LintRiddleState),
+    SolveRiddle(SolveRiddleState),
+    RiddleStats(RiddleStatsState),
+    StartRace(StartRaceState),
+    EndRace(EndRaceState),
 }
 
-fn admins_from_env() -> HashSet<UserId> {
+// read at build time, the other settings are read when the bot starts
+pub(crate) fn admins_from_env() -> HashSet<UserId> {
     option_env!("ADMINS")
         .unwrap_or("")
         .split(',')
@@ -68,6 +228,10 @@ fn admins_from_env() -> HashSet<UserId> {
         .collect::<HashSet<_>>()
 }
 
+pub(crate) fn is_admin(user_id: UserId) -> bool {
+    admins_from_env().contains(&user_id)
+}
+
 pub(crate) fn dependencies() -> DependencyMap {
     dptree::deps![ChatData::<DialogueState>::default()]
 }
@@ -77,7 +241,8 @@ pub(crate) fn schema() -> UpdateHandler<Error> {
 
     let admins = admins_from_env();
 
-    let is_admin = move |msg: Message| admins.contains(&msg.from().as_ref().unwrap().id);
+    // channel posts have no sender
+    let is_admin = move |msg: Message| msg.from().is_some_and(|user| admins.contains(&user.id));
 
     Update::filter_message()
         .filter(is_admin)
@@ -108,6 +273,118 @@ pub(crate) fn schema() -> UpdateHandler<Error> {
                             RemoveRiddleState::Code,
                         )))
                         .endpoint(command_remove_riddle),
+                )
+                .branch(
+                    case![AdminCommand::ExportRiddle]
+                        .inspect_async(riddles::update_data_func(DialogueState::ExportRiddle(
+                            ExportRiddleState::Code,
+                        )))
+                        .endpoint(command_export_riddle),
+                )
+                .branch(
+                    case![AdminCommand::ImportRiddle]
+                        .inspect_async(riddles::update_data_func(DialogueState::ImportRiddle(
+                            ImportRiddleState::Code,
+                        )))
+                        .endpoint(command_new_riddle),
+                )
+                .branch(
+                    case![AdminCommand::GraphRiddle]
+                        .inspect_async(riddles::update_data_func(DialogueState::GraphRiddle(
+                            GraphRiddleState::Code,
+                        )))
+                        .endpoint(command_export_riddle),
+                )
+                .branch(
+                    case![AdminCommand::EditRiddle]
+                        .inspect_async(riddles::update_data_func(DialogueState::EditRiddle(
+                            EditRiddleState::Code,
+                        )))
+                        .endpoint(command_export_riddle),
+                )
+                .branch(
+                    case![AdminCommand::RiddleVersions]
+                        .inspect_async(riddles::update_data_func(DialogueState::RiddleVersions(
+                            RiddleVersionsState::Code,
+                        )))
+                        .endpoint(command_export_riddle),
+                )
+                .branch(
+                    case![AdminCommand::DiffRiddle]
+                        .inspect_async(riddles::update_data_func(DialogueState::DiffRiddle(
+                            DiffRiddleState::Code,
+                        )))
+                        .endpoint(command_export_riddle),
+                )
+                .branch(
+                    case![AdminCommand::RollbackRiddle]
+                        .inspect_async(riddles::update_data_func(DialogueState::RollbackRiddle(
+                            RollbackRiddleState::Code,
+                        )))
+                        .endpoint(command_export_riddle),
+                )
+                .branch(
+                    case![AdminCommand::PublishRiddle]
+                        .inspect_async(riddles::update_data_func(DialogueState::PublishRiddle(
+                            PublishRiddleState::Code { published: true },
+                        )))
+                        .endpoint(command_export_riddle),
+                )
+                .branch(
+                    case![AdminCommand::UnpublishRiddle]
+                        .inspect_async(riddles::update_data_func(DialogueState::PublishRiddle(
+                            PublishRiddleState::Code { published: false },
+                        )))
+                        .endpoint(command_export_riddle),
+                )
+                .branch(
+                    case![AdminCommand::CatalogRiddle]
+                        .inspect_async(riddles::update_data_func(DialogueState::CatalogRiddle(
+                            CatalogRiddleState::Code,
+                        )))
+                        .endpoint(command_export_riddle),
+                )
+                .branch(
+                    case![AdminCommand::UncatalogRiddle]
+                        .inspect_async(riddles::update_data_func(DialogueState::UncatalogRiddle(
+                            UncatalogRiddleState::Code,
+                        )))
+                        .endpoint(command_export_riddle),
+                )
+                .branch(
+                    case![AdminCommand::LintRiddle]
+                        .inspect_async(riddles::update_data_func(DialogueState::LintRiddle(
+                            LintRiddleState::Code,
+                        )))
+                        .endpoint(command_export_riddle),
+                )
+                .branch(
+                    case![AdminCommand::SolveRiddle]
+                        .inspect_async(riddles::update_data_func(DialogueState::SolveRiddle(
+                            SolveRiddleState::Code,
+                        )))
+                        .endpoint(command_export_riddle),
+                )
+                .branch(
+                    case![AdminCommand::RiddleStats]
+                        .inspect_async(riddles::update_data_func(DialogueState::RiddleStats(
+                            RiddleStatsState::Code,
+                        )))
+                        .endpoint(command_export_riddle),
+                )
+                .branch(
+                    case![AdminCommand::StartRace]
+                        .inspect_async(riddles::update_data_func(DialogueState::StartRace(
+                            StartRaceState::Code,
+                        )))
+                        .endpoint(command_export_riddle),
+                )
+                .branch(
+                    case![AdminCommand::EndRace]
+                        .inspect_async(riddles::update_data_func(DialogueState::EndRace(
+                            EndRaceState::Code,
+                        )))
+                        .endpoint(command_export_riddle),
                 ),
         )
         .branch(
@@ -133,6 +410,118 @@ pub(crate) fn schema() -> UpdateHandler<Error> {
                     case![DialogueState::RemoveRiddle(remove_riddle_state)]
                         .branch(case![RemoveRiddleState::Code])
                         .endpoint(remove_riddle_code),
+                )
+                .branch(
+                    case![DialogueState::ExportRiddle(export_riddle_state)]
+                        .branch(case![ExportRiddleState::Code])
+                        .endpoint(export_riddle_code),
+                )
+                .branch(
+                    case![DialogueState::ImportRiddle(import_riddle_state)]
+                        .branch(case![ImportRiddleState::Code].endpoint(import_riddle_code))
+                        .branch(
+                            case![ImportRiddleState::Definition { code }]
+                                .endpoint(import_riddle_definition),
+                        ),
+                )
+                .branch(
+                    case![DialogueState::GraphRiddle(graph_riddle_state)]
+                        .branch(case![GraphRiddleState::Code].endpoint(graph_riddle_code))
+                        .branch(
+                            case![GraphRiddleState::Format { code }].endpoint(graph_riddle_format),
+                        ),
+                )
+                .branch(
+                    case![DialogueState::EditRiddle(edit_riddle_state)]
+                        .branch(case![EditRiddleState::Code].endpoint(edit_riddle_code))
+                        .branch(case![EditRiddleState::Field { code }].endpoint(edit_riddle_field))
+                        .branch(
+                            case![EditRiddleState::Value { code, field }]
+                                .endpoint(edit_riddle_value),
+                        )
+                        .branch(
+                            case![EditRiddleState::Remap { code, version }]
+                                .endpoint(edit_riddle_remap),
+                        ),
+                )
+                .branch(
+                    case![DialogueState::RiddleVersions(riddle_versions_state)]
+                        .branch(case![RiddleVersionsState::Code])
+                        .endpoint(riddle_versions_code),
+                )
+                .branch(
+                    case![DialogueState::DiffRiddle(diff_riddle_state)]
+                        .branch(case![DiffRiddleState::Code].endpoint(diff_riddle_code))
+                        .branch(
+                            case![DiffRiddleState::Versions { code }]
+                                .endpoint(diff_riddle_versions),
+                        ),
+                )
+                .branch(
+                    case![DialogueState::RollbackRiddle(rollback_riddle_state)]
+                        .branch(case![RollbackRiddleState::Code].endpoint(rollback_riddle_code))
+                        .branch(
+                            case![RollbackRiddleState::Version { code }]
+                                .endpoint(rollback_riddle_version),
+                        ),
+                )
+                .branch(
+                    case![DialogueState::PublishRiddle(publish_riddle_state)].branch(
+                        case![PublishRiddleState::Code { published }].endpoint(publish_riddle_code),
+                    ),
+                )
+                .branch(
+                    case![DialogueState::CatalogRiddle(catalog_riddle_state)]
+                        .branch(case![CatalogRiddleState::Code].endpoint(catalog_riddle_code))
+                        .branch(
+                            case![CatalogRiddleState::Tags { code }].endpoint(catalog_riddle_tags),
+                        )
+                        .branch(
+                            case![CatalogRiddleState::Difficulty { code, tags }]
+                                .endpoint(catalog_riddle_difficulty),
+                        )
+                        .branch(
+                            case![CatalogRiddleState::Blurb {
+                                code,
+                                tags,
+                                difficulty
+                            }]
+                            .endpoint(catalog_riddle_blurb),
+                        ),
+                )
+                .branch(
+                    case![DialogueState::UncatalogRiddle(uncatalog_riddle_state)]
+                        .branch(case![UncatalogRiddleState::Code])
+                        .endpoint(uncatalog_riddle_code),
+                )
+                .branch(
+                    case![DialogueState::LintRiddle(lint_riddle_state)]
+                        .branch(case![LintRiddleState::Code])
+                        .endpoint(lint_riddle_code),
+                )
+                .branch(
+                    case![DialogueState::SolveRiddle(solve_riddle_state)]
+                        .branch(case![SolveRiddleState::Code])
+                        .endpoint(solve_riddle_code),
+                )
+                .branch(
+                    case![DialogueState::RiddleStats(riddle_stats_state)]
+                        .branch(case![RiddleStatsState::Code])
+                        .endpoint(riddle_stats_code),
+                )
+                .branch(
+                    case![DialogueState::StartRace(start_race_state)]
+                        .branch(case![StartRaceState::Code].endpoint(start_race_code))
+                        .branch(case![StartRaceState::Teams { code }].endpoint(start_race_teams))
+                        .branch(
+                            case![StartRaceState::Announcements { code, teams }]
+                                .endpoint(start_race_announcements),
+                        ),
+                )
+                .branch(
+                    case![DialogueState::EndRace(end_race_state)]
+                        .branch(case![EndRaceState::Code])
+                        .endpoint(end_race_code),
                 ),
         )
 }
@@ -154,11 +543,21 @@ async fn command_list_riddles(bot: Bot, riddles: riddles::Riddles, msg: Message)
         .iter()
         .map(|(code, riddle)| {
             format!(
-                "{} \\(code: `{}`\\)\n[Author](tg://user?id={})\nDescription:\n{}",
-                escape_chars(riddle.name.clone()),
+                "{} \\(code: `{}`, {}{}\\)\n[Author](tg://user?id={})\nDescription:\n{}",
+                escape_chars(riddle.current().name.clone()),
                 code,
+                if riddle.published {
+                    "published"
+                } else {
+                    "draft"
+                },
+                if riddle.listing.is_some() {
+                    ", listed"
+                } else {
+                    ""
+                },
                 riddle.creator,
-                escape_chars(riddle.description.clone())
+                escape_chars(riddle.current().description.clone())
             )
         })
         .fold("List of riddles:".to_owned(), |acc, s| acc + "\n\n" + &s);
@@ -189,6 +588,11 @@ async fn command_remove_riddle(bot: Bot, msg: Message) -> HandlerResult {
     Ok(())
 }
 
+async fn command_export_riddle(bot: Bot, msg: Message) -> HandlerResult {
+    send_message(&bot, msg.chat.id, "What is the code of the riddle?").await?;
+    Ok(())
+}
+
 async fn new_riddle_code(
     bot: Bot,
     msg: Message,
@@ -253,7 +657,7 @@ async fn new_riddle_description(
     send_message(
         &bot,
         msg.chat.id,
-        "What is the state machine code of the riddle?",
+        "What is the state machine code of the riddle? (JSON or the text format, as a message or a document)",
     )
     .await?;
     riddles::update_data(
@@ -277,6 +681,119 @@ fn random_string() -> String {
         .collect()
 }
 
+/// Reads a definition either from the message text or from an attached
+/// document.
+async fn read_source(bot: &Bot, msg: &Message) -> HandlerResult<(String, models_raw::Format)> {
+    if let Some(document) = msg.document() {
+        let source = download_document(bot, document).await?;
+        let format = models_raw::Format::detect(document.file_name.as_deref(), &source);
+        Ok((source, format))
+    } else if let Some(text) = msg.text() {
+        Ok((text.to_owned(), models_raw::Format::detect(None, text)))
+    } else {
+        Err("Send the definition as a text message or as a document".into())
+    }
+}
+
+/// Builds the state machine and runs the playthroughs that came with it,
+/// riddles with errors or failing playthroughs are not saved.
+async fn compile(
+    state_machine_raw: models_raw::StateMachine,
+) -> HandlerResult<state_machine::StateMachine> {
+    if let Err(e) = lints::validate(&state_machine_raw) {
+        return Err(format!("The state machine has errors:\n{}", e).into());
+    }
+
+    let state_machine = state_machine::StateMachine::new(state_machine_raw);
+
+    let failures = playthroughs::run_all(&state_machine).await?;
+    if !failures.is_empty() {
+        return Err(format!("Riddle tests failed:\n{}", failures.join("\n")).into());
+    }
+
+    Ok(state_machine)
+}
+
+async fn read_state_machine(
+    bot: &Bot,
+    msg: &Message,
+) -> HandlerResult<state_machine::StateMachine> {
+    let (source, format) = read_source(bot, msg).await?;
+    compile(models_raw::parse_state_machine(&source, format)?).await
+}
+
+/// Warnings about the state machine, one per line, empty if there are none.
+fn lint_report(state_machine: &state_machine::StateMachine) -> String {
+    let mut report: String = lints::check(&state_machine.to_raw())
+        .iter()
+        .map(|lint| format!("\n{}", lint))
+        .collect();
+    if let Err(unsolvable) = solver::shortest_solution(state_machine) {
+        report += &format!("\nwarning: the riddle can't be solved: {}", unsolvable);
+    }
+    report
+}
+
+async fn read_riddle(bot: &Bot, msg: &Message) -> HandlerResult<models_raw::Riddle> {
+    match read_source(bot, msg).await? {
+        (source, models_raw::Format::Json) => Ok(serde_json::from_str(&source)?),
+        (_, models_raw::Format::Text) => Err("Riddle definitions must be in JSON".into()),
+    }
+}
+
+/// Stores the riddle under the given code, or under a random one if there
+/// is no code, and tells the admin which code was used.
+async fn insert_riddle(
+    bot: &Bot,
+    chat_id: ChatId,
+    code: Option<String>,
+    riddle: riddles::Riddle,
+    riddles_mut: riddles::Riddles,
+) -> HandlerResult {
+    let report = lint_report(&riddle.current().state_machine);
+
+    let code = match code {
+        Some(code) => {
+            let mut riddles = riddles_mut.lock().await;
+            if riddles.contains_key(&code) {
+                send_message(
+                    bot,
+                    chat_id,
+                    format!("Riddle with code `{}` already exists!", code),
+                )
+                .await?;
+                return Ok(());
+            }
+
+            riddles.insert(code.clone(), riddle);
+            code
+        }
+        None => {
+            let mut riddles = riddles_mut.lock().await;
+            let code = loop {
+                let code = random_string();
+                if !riddles.contains_key(&code) {
+                    break code;
+                }
+            };
+
+            riddles.insert(code.clone(), riddle);
+            code
+        }
+    };
+
+    send_message(
+        bot,
+        chat_id,
+        format!(
+            "Riddle created as a draft! Code: `{}`\nUse /publishriddle to make it playable for everyone.{}",
+            code, report
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
 async fn new_riddle_state_machine(
     bot: Bot,
     msg: Message,
@@ -284,52 +801,20 @@ async fn new_riddle_state_machine(
     (code, name, description): (Option<String>, RiddleName, RiddleDescription),
     riddles_mut: riddles::Riddles,
 ) -> HandlerResult {
-    let state_machine_str = msg.text().unwrap();
-
-    match serde_json::from_str::<models_raw::StateMachine>(state_machine_str) {
+    match read_state_machine(&bot, &msg).await {
         Ok(state_machine) => {
             let chat_id = msg.chat.id;
 
             riddles::update_data(DialogueState::None, msg.clone(), dialogue_state_mut).await;
 
-            let riddle = riddles::Riddle {
-                name: name.0,
-                description: description.0,
-                creator: msg.from().unwrap().id,
-                state_machine: state_machine::StateMachine::new(state_machine),
-            };
-
-            let code = match code {
-                Some(code) => {
-                    let mut riddles = riddles_mut.lock().await;
-                    if riddles.contains_key(&code) {
-                        send_message(
-                            &bot,
-                            chat_id,
-                            format!("Riddle with code `{}` already exists!", code),
-                        )
-                        .await?;
-                        return Ok(());
-                    }
-
-                    riddles.insert(code.clone(), riddle);
-                    code
-                }
-                None => {
-                    let mut riddles = riddles_mut.lock().await;
-                    let code = loop {
-                        let code = random_string();
-                        if !riddles.contains_key(&code) {
-                            break code;
-                        }
-                    };
-
-                    riddles.insert(code.clone(), riddle);
-                    code
-                }
-            };
+            let riddle = riddles::Riddle::new(riddles::RiddleVersion::new(
+                name.0,
+                description.0,
+                state_machine,
+                msg.from().unwrap().id,
+            ));
 
-            send_message(&bot, chat_id, format!("Riddle created\n! Code: `{}`", code)).await?;
+            insert_riddle(&bot, chat_id, code, riddle, riddles_mut).await?;
         }
         Err(e) => {
             send_message(&bot, msg.chat.id, format!("Error: {}", e)).await?;
@@ -339,21 +824,1235 @@ async fn new_riddle_state_machine(
     Ok(())
 }
 
-async fn remove_riddle_code(
+async fn import_riddle_code(
     bot: Bot,
     msg: Message,
     dialogue_state_mut: ChatData<DialogueState>,
     riddles_mut: riddles::Riddles,
 ) -> HandlerResult {
     let code = msg.text().unwrap();
-
-    let mut riddles = riddles_mut.lock().await;
-    if riddles.remove(code).is_some() {
-        riddles::update_data(DialogueState::None, msg.clone(), dialogue_state_mut).await;
-        send_message(&bot, msg.chat.id, "Riddle removed!").await?;
-    } else {
-        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+    let chat_id = msg.chat.id;
+    let riddles = riddles_mut.lock().await;
+    if riddles.contains_key(code) {
+        send_message(
+            &bot,
+            chat_id,
+            format!("Riddle with code `{}` already exists!", code),
+        )
+        .await?;
+        return Ok(());
     }
+    riddles::update_data(
+        DialogueState::ImportRiddle(ImportRiddleState::Definition {
+            code: if code == RANDOM_RIDDLE_CODE {
+                None
+            } else {
+                Some(code.to_owned())
+            },
+        }),
+        msg,
+        dialogue_state_mut,
+    )
+    .await;
+    send_message(
+        &bot,
+        chat_id,
+        "What is the riddle's definition? (as exported by /exportriddle)",
+    )
+    .await?;
+    Ok(())
+}
 
+async fn import_riddle_definition(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    code: Option<String>,
+    riddles_mut: riddles::Riddles,
+) -> HandlerResult {
+    let definition = match read_riddle(&bot, &msg).await {
+        Ok(definition) => compile(definition.state_machine)
+            .await
+            .map(|state_machine| (definition.name, definition.description, state_machine)),
+        Err(e) => Err(e),
+    };
+
+    match definition {
+        Ok((name, description, state_machine)) => {
+            let chat_id = msg.chat.id;
+
+            riddles::update_data(DialogueState::None, msg.clone(), dialogue_state_mut).await;
+
+            let riddle = riddles::Riddle::new(riddles::RiddleVersion::new(
+                name,
+                description,
+                state_machine,
+                msg.from().unwrap().id,
+            ));
+
+            insert_riddle(&bot, chat_id, code, riddle, riddles_mut).await?;
+        }
+        Err(e) => {
+            send_message(&bot, msg.chat.id, format!("Error: {}", e)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn export_riddle_code(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    riddles: riddles::Riddles,
+) -> HandlerResult {
+    let code = msg.text().unwrap();
+
+    let riddles = riddles.lock().await;
+    match riddles.get(code) {
+        None => {
+            send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        }
+        Some(riddle) => {
+            let definition = serde_json::to_string_pretty(&riddle.current().to_raw())?;
+            bot.send_document(
+                msg.chat.id,
+                InputFile::memory(definition).file_name(format!("{}.json", code)),
+            )
+            .await?;
+            riddles::update_data(DialogueState::None, msg.clone(), dialogue_state_mut).await;
+        }
+    }
+
+    Ok(())
+}
+
+async fn graph_riddle_code(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    riddles: riddles::Riddles,
+) -> HandlerResult {
+    let code = msg.text().unwrap();
+
+    if !riddles.lock().await.contains_key(code) {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    }
+
+    send_message(&bot, msg.chat.id, "Which format? (dot or mermaid)").await?;
+    riddles::update_data(
+        DialogueState::GraphRiddle(GraphRiddleState::Format {
+            code: code.to_owned(),
+        }),
+        msg,
+        dialogue_state_mut,
+    )
+    .await;
+    Ok(())
+}
+
+async fn graph_riddle_format(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    code: String,
+    riddles: riddles::Riddles,
+) -> HandlerResult {
+    let format = match msg.text().and_then(graph::Format::from_name) {
+        Some(format) => format,
+        None => {
+            send_message(&bot, msg.chat.id, "Unknown format, use dot or mermaid").await?;
+            return Ok(());
+        }
+    };
+
+    let riddles = riddles.lock().await;
+    match riddles.get(&code) {
+        None => {
+            send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        }
+        Some(riddle) => {
+            let diagram = graph::render(&riddle.current().state_machine, format);
+            bot.send_document(
+                msg.chat.id,
+                InputFile::memory(diagram).file_name(format!("{}.{}", code, format.extension())),
+            )
+            .await?;
+        }
+    }
+    riddles::update_data(DialogueState::None, msg, dialogue_state_mut).await;
+
+    Ok(())
+}
+
+async fn edit_riddle_code(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    riddles: riddles::Riddles,
+) -> HandlerResult {
+    let code = msg.text().unwrap();
+
+    if !riddles.lock().await.contains_key(code) {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    }
+
+    send_message(
+        &bot,
+        msg.chat.id,
+        "What do you want to change? (name, description or state_machine)",
+    )
+    .await?;
+    riddles::update_data(
+        DialogueState::EditRiddle(EditRiddleState::Field {
+            code: code.to_owned(),
+        }),
+        msg,
+        dialogue_state_mut,
+    )
+    .await;
+    Ok(())
+}
+
+async fn edit_riddle_field(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    code: String,
+) -> HandlerResult {
+    let (field, question) = match msg.text().map(str::trim) {
+        Some("name") => (RiddleField::Name, "What is the new name of the riddle?"),
+        Some("description") => (
+            RiddleField::Description,
+            "What is the new description of the riddle?",
+        ),
+        Some("state_machine") => (
+            RiddleField::StateMachine,
+            "What is the new state machine of the riddle?",
+        ),
+        _ => {
+            send_message(
+                &bot,
+                msg.chat.id,
+                "Unknown field, use name, description or state_machine",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+
+    send_message(&bot, msg.chat.id, question).await?;
+    riddles::update_data(
+        DialogueState::EditRiddle(EditRiddleState::Value { code, field }),
+        msg,
+        dialogue_state_mut,
+    )
+    .await;
+    Ok(())
+}
+
+async fn edit_riddle_value(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    (code, field): (String, RiddleField),
+    riddles_mut: riddles::Riddles,
+    players: ChatData<riddles::Sessions>,
+) -> HandlerResult {
+    let chat_id = msg.chat.id;
+    let author = msg.from().unwrap().id;
+
+    let state_machine = match field {
+        RiddleField::StateMachine => match read_state_machine(&bot, &msg).await {
+            Ok(state_machine) => Some(state_machine),
+            Err(e) => {
+                send_message(&bot, chat_id, format!("Error: {}", e)).await?;
+                return Ok(());
+            }
+        },
+        _ => None,
+    };
+
+    let mut riddles = riddles_mut.lock().await;
+    let Some(riddle) = riddles.get_mut(&code) else {
+        send_message(&bot, chat_id, "Riddle not found!").await?;
+        return Ok(());
+    };
+
+    let mut version = riddle.current().copy(author);
+    match field {
+        RiddleField::Name => version.name = msg.text().unwrap().to_owned(),
+        RiddleField::Description => version.description = msg.text().unwrap().to_owned(),
+        RiddleField::StateMachine => version.state_machine = Arc::new(state_machine.unwrap()),
+    }
+
+    let removed_states = riddle
+        .current()
+        .state_machine
+        .removed_states(&version.state_machine);
+    let lints = lint_report(&version.state_machine);
+    let new_state_machine = version.state_machine.clone();
+    let number = riddle.push_version(version);
+    drop(riddles);
+
+    let players = commands::riddle_players(&players, &code).await;
+    let stranded = players
+        .iter()
+        .filter(|(_, chat_state)| !new_state_machine.has_state(&chat_state.state))
+        .count();
+    let mut report = format!(
+        "Riddle updated to version {}! {} player(s) in progress stay on their version until they finish.",
+        number,
+        players.len()
+    );
+    if !removed_states.is_empty() {
+        report += &format!("\nStates no longer present: {}", removed_states.join(", "));
+    }
+    if let RiddleField::StateMachine = field {
+        report += &lints;
+    }
+
+    let next_state = if stranded == 0 {
+        DialogueState::None
+    } else {
+        report += &format!(
+            "\n\n{} player(s) are in states that version {} no longer has. \
+            Which state of version {} should they be moved to? \
+            ({} to stop their riddle, {} to leave them on their version)",
+            stranded, number, number, STOP_RIDDLE, KEEP_VERSION
+        );
+        DialogueState::EditRiddle(EditRiddleState::Remap {
+            code,
+            version: number,
+        })
+    };
+    riddles::update_data(next_state, msg, dialogue_state_mut).await;
+    send_message(&bot, chat_id, report).await?;
+    Ok(())
+}
+
+static STOP_RIDDLE: &str = "STOP";
+static KEEP_VERSION: &str = "KEEP";
+
+/// Moves the players who are in states the new version doesn't have to one
+/// of its states, stops their riddle or leaves them pinned to their version.
+async fn edit_riddle_remap(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    (code, version): (String, usize),
+    riddles: riddles::Riddles,
+    players: ChatData<riddles::Sessions>,
+) -> HandlerResult {
+    let chat_id = msg.chat.id;
+    let Some(target) = msg.text().map(str::trim) else {
+        send_message(
+            &bot,
+            chat_id,
+            format!("Send a state name, {} or {}", STOP_RIDDLE, KEEP_VERSION),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    if target == KEEP_VERSION {
+        riddles::update_data(DialogueState::None, msg, dialogue_state_mut).await;
+        send_message(
+            &bot,
+            chat_id,
+            "The players stay on their version until they finish.",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let Some(state_machine) = riddles
+        .lock()
+        .await
+        .get(&code)
+        .and_then(|riddle| riddle.version(version))
+        .map(|version| version.state_machine.clone())
+    else {
+        riddles::update_data(DialogueState::None, msg, dialogue_state_mut).await;
+        send_message(&bot, chat_id, "Riddle not found!").await?;
+        return Ok(());
+    };
+
+    let to = if target == STOP_RIDDLE {
+        None
+    } else if state_machine.has_state(target) {
+        Some(target)
+    } else {
+        send_message(&bot, chat_id, format!("State `{}` does not exist", target)).await?;
+        return Ok(());
+    };
+
+    let moved = commands::move_players(&players, &code, &state_machine, version, to).await;
+    let notice = match to {
+        Some(_) => "The riddle has been changed, your progress has been moved to a new place.",
+        None => "The riddle has been changed and your progress could not be kept. Riddle stopped.",
+    };
+    for key in &moved {
+        // the player's chat may have removed the bot
+        if let Err(e) = send_message(&bot, key.chat, notice).await {
+            log::warn!("Cannot tell {} that riddle {} changed: {}", key, code, e);
+        }
+    }
+
+    riddles::update_data(DialogueState::None, msg, dialogue_state_mut).await;
+    send_message(
+        &bot,
+        chat_id,
+        format!("Riddle updated! {} player(s) affected.", moved.len()),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn riddle_versions_code(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    riddles: riddles::Riddles,
+    players: ChatData<riddles::Sessions>,
+) -> HandlerResult {
+    let code = msg.text().unwrap();
+
+    let players = commands::riddle_players(&players, code).await;
+    let riddles = riddles.lock().await;
+    let Some(riddle) = riddles.get(code) else {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    };
+
+    let versions_string = riddle
+        .versions()
+        .map(|(number, version)| {
+            let playing = players
+                .iter()
+                .filter(|(_, chat_state)| chat_state.version == number)
+                .count();
+            format!(
+                "v{}{}: {}\nby {} at {}, {} player(s) in progress",
+                number,
+                if number == riddle.current_number() {
+                    " (current)"
+                } else {
+                    ""
+                },
+                version.name,
+                version.author,
+                version.created_at.format("%Y-%m-%d %H:%M UTC"),
+                playing
+            )
+        })
+        .fold("Versions:".to_owned(), |acc, s| acc + "\n\n" + &s);
+
+    send_message(&bot, msg.chat.id, versions_string).await?;
+    riddles::update_data(DialogueState::None, msg.clone(), dialogue_state_mut).await;
+    Ok(())
+}
+
+async fn diff_riddle_code(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    riddles: riddles::Riddles,
+) -> HandlerResult {
+    let code = msg.text().unwrap();
+
+    if !riddles.lock().await.contains_key(code) {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    }
+
+    send_message(
+        &bot,
+        msg.chat.id,
+        "Which versions do you want to compare? (e.g. `1 2`)",
+    )
+    .await?;
+    riddles::update_data(
+        DialogueState::DiffRiddle(DiffRiddleState::Versions {
+            code: code.to_owned(),
+        }),
+        msg,
+        dialogue_state_mut,
+    )
+    .await;
+    Ok(())
+}
+
+async fn diff_riddle_versions(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    code: String,
+    riddles: riddles::Riddles,
+) -> HandlerResult {
+    let numbers: Vec<usize> = msg
+        .text()
+        .unwrap()
+        .split_whitespace()
+        .flat_map(str::parse)
+        .collect();
+
+    let riddles_lock = riddles.lock().await;
+    let Some(riddle) = riddles_lock.get(&code) else {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    };
+
+    let (old, new) = match numbers.as_slice() {
+        [old, new] => match (riddle.version(*old), riddle.version(*new)) {
+            (Some(old), Some(new)) => (old, new),
+            _ => {
+                send_message(&bot, msg.chat.id, "Version not found!").await?;
+                return Ok(());
+            }
+        },
+        _ => {
+            send_message(&bot, msg.chat.id, "Send two version numbers").await?;
+            return Ok(());
+        }
+    };
+
+    let mut changes = vec![];
+    if old.name != new.name {
+        changes.push(format!("~ name: {} -> {}", old.name, new.name));
+    }
+    if old.description != new.description {
+        changes.push("~ description".to_owned());
+    }
+    changes.extend(old.state_machine.diff(&new.state_machine));
+
+    let diff_string = if changes.is_empty() {
+        "The versions are identical".to_owned()
+    } else {
+        changes.join("\n")
+    };
+    send_message(&bot, msg.chat.id, diff_string).await?;
+    drop(riddles_lock);
+
+    riddles::update_data(DialogueState::None, msg, dialogue_state_mut).await;
+    Ok(())
+}
+
+async fn rollback_riddle_code(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    riddles: riddles::Riddles,
+) -> HandlerResult {
+    let code = msg.text().unwrap();
+
+    if !riddles.lock().await.contains_key(code) {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    }
+
+    send_message(
+        &bot,
+        msg.chat.id,
+        "Which version do you want to roll back to?",
+    )
+    .await?;
+    riddles::update_data(
+        DialogueState::RollbackRiddle(RollbackRiddleState::Version {
+            code: code.to_owned(),
+        }),
+        msg,
+        dialogue_state_mut,
+    )
+    .await;
+    Ok(())
+}
+
+async fn rollback_riddle_version(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    code: String,
+    riddles_mut: riddles::Riddles,
+) -> HandlerResult {
+    let number = msg.text().unwrap().trim().parse::<usize>().ok();
+    let author = msg.from().unwrap().id;
+
+    let mut riddles = riddles_mut.lock().await;
+    let Some(riddle) = riddles.get_mut(&code) else {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    };
+    let Some(version) = number.and_then(|number| riddle.version(number)) else {
+        send_message(&bot, msg.chat.id, "Version not found!").await?;
+        return Ok(());
+    };
+
+    // the history is immutable, so a rollback is a new copy of the old version
+    let version = version.copy(author);
+    let new_number = riddle.push_version(version);
+    drop(riddles);
+
+    send_message(
+        &bot,
+        msg.chat.id,
+        format!(
+            "Riddle rolled back to version {} as version {}",
+            number.unwrap(),
+            new_number
+        ),
+    )
+    .await?;
+    riddles::update_data(DialogueState::None, msg, dialogue_state_mut).await;
+    Ok(())
+}
+
+/// A `t.me` link that starts the riddle, if its code can be put in a link:
+/// start payloads are limited to 64 letters, digits, `_` and `-`.
+async fn start_link(bot: &Bot, code: &str) -> HandlerResult<Option<String>> {
+    let linkable = code.len() <= 64
+        && code
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !linkable {
+        return Ok(None);
+    }
+    let me = bot.get_me().await?;
+    Ok(Some(format!(
+        "https://t.me/{}?start={}",
+        me.username(),
+        code
+    )))
+}
+
+async fn publish_riddle_code(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    published: bool,
+    riddles_mut: riddles::Riddles,
+) -> HandlerResult {
+    let code = msg.text().unwrap();
+
+    let mut riddles = riddles_mut.lock().await;
+    let Some(riddle) = riddles.get_mut(code) else {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    };
+    riddle.published = published;
+    drop(riddles);
+
+    let answer = if published {
+        let mut answer = "Riddle published! Everyone can play it now.".to_owned();
+        if let Some(link) = start_link(&bot, code).await? {
+            answer += &format!("\nPlayers can start it with this link: {}", link);
+        }
+        answer
+    } else {
+        "Riddle unpublished! Only its creator and admins can start it, players in progress can finish it.".to_owned()
+    };
+    send_message(&bot, msg.chat.id, answer).await?;
+    riddles::update_data(DialogueState::None, msg.clone(), dialogue_state_mut).await;
+    Ok(())
+}
+
+// tags end up in callback data of the catalog's buttons, which is limited
+const MAX_TAGS: usize = 5;
+const MAX_TAG_LENGTH: usize = 20;
+const MAX_BLURB_LENGTH: usize = 300;
+
+async fn catalog_riddle_code(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    riddles: riddles::Riddles,
+) -> HandlerResult {
+    let code = msg.text().unwrap();
+
+    if !riddles.lock().await.contains_key(code) {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    }
+    // the catalog's start buttons carry the code
+    if commands::start_callback(code).is_none() {
+        send_message(
+            &bot,
+            msg.chat.id,
+            "This riddle's code is too long to be listed, create the riddle again with a shorter code",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    send_message(
+        &bot,
+        msg.chat.id,
+        "Send the riddle's tags, separated by commas, or - for none",
+    )
+    .await?;
+    riddles::update_data(
+        DialogueState::CatalogRiddle(CatalogRiddleState::Tags {
+            code: code.to_owned(),
+        }),
+        msg,
+        dialogue_state_mut,
+    )
+    .await;
+    Ok(())
+}
+
+async fn catalog_riddle_tags(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    code: String,
+) -> HandlerResult {
+    let text = msg.text().unwrap().trim();
+    let mut tags: Vec<String> = vec![];
+    if text != "-" {
+        for tag in text.split(',').map(|tag| tag.trim().to_lowercase()) {
+            if !tag.is_empty() && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+    }
+
+    if tags.len() > MAX_TAGS
+        || tags
+            .iter()
+            .any(|tag| tag.len() > MAX_TAG_LENGTH || tag.contains(char::is_whitespace))
+    {
+        send_message(
+            &bot,
+            msg.chat.id,
+            format!(
+                "At most {} tags of at most {} characters without spaces, try again",
+                MAX_TAGS, MAX_TAG_LENGTH
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    send_message(
+        &bot,
+        msg.chat.id,
+        "How difficult is the riddle? (easy, medium or hard)",
+    )
+    .await?;
+    riddles::update_data(
+        DialogueState::CatalogRiddle(CatalogRiddleState::Difficulty { code, tags }),
+        msg,
+        dialogue_state_mut,
+    )
+    .await;
+    Ok(())
+}
+
+async fn catalog_riddle_difficulty(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    (code, tags): (String, Vec<String>),
+) -> HandlerResult {
+    let Some(difficulty) = riddles::Difficulty::parse(msg.text().unwrap()) else {
+        send_message(&bot, msg.chat.id, "Expected easy, medium or hard").await?;
+        return Ok(());
+    };
+
+    send_message(
+        &bot,
+        msg.chat.id,
+        "Send a short blurb that makes players want to try the riddle",
+    )
+    .await?;
+    riddles::update_data(
+        DialogueState::CatalogRiddle(CatalogRiddleState::Blurb {
+            code,
+            tags,
+            difficulty,
+        }),
+        msg,
+        dialogue_state_mut,
+    )
+    .await;
+    Ok(())
+}
+
+async fn catalog_riddle_blurb(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    (code, tags, difficulty): (String, Vec<String>, riddles::Difficulty),
+    riddles_mut: riddles::Riddles,
+) -> HandlerResult {
+    let blurb = msg.text().unwrap().trim().to_owned();
+    if blurb.chars().count() > MAX_BLURB_LENGTH {
+        send_message(
+            &bot,
+            msg.chat.id,
+            format!(
+                "The blurb must be at most {} characters long",
+                MAX_BLURB_LENGTH
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let mut riddles = riddles_mut.lock().await;
+    let Some(riddle) = riddles.get_mut(&code) else {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    };
+    riddle.listing = Some(riddles::Listing {
+        tags,
+        difficulty,
+        blurb,
+    });
+    let published = riddle.published;
+    drop(riddles);
+
+    let answer = if published {
+        "Riddle listed! Players can find it with /browse."
+    } else {
+        "Riddle listed! It will show up in /browse once it is published."
+    };
+    send_message(&bot, msg.chat.id, answer).await?;
+    riddles::update_data(DialogueState::None, msg, dialogue_state_mut).await;
+    Ok(())
+}
+
+async fn uncatalog_riddle_code(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    riddles_mut: riddles::Riddles,
+) -> HandlerResult {
+    let code = msg.text().unwrap();
+
+    let mut riddles = riddles_mut.lock().await;
+    let Some(riddle) = riddles.get_mut(code) else {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    };
+    riddle.listing = None;
+    drop(riddles);
+
+    send_message(
+        &bot,
+        msg.chat.id,
+        "Riddle removed from the catalog! Players can still start it by its code.",
+    )
+    .await?;
+    riddles::update_data(DialogueState::None, msg.clone(), dialogue_state_mut).await;
+    Ok(())
+}
+
+async fn remove_riddle_code(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    riddles_mut: riddles::Riddles,
+    players: ChatData<riddles::Sessions>,
+) -> HandlerResult {
+    let code = msg.text().unwrap();
+
+    let Some(riddle) = riddles_mut.lock().await.remove(code) else {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    };
+
+    // sessions of the riddle can't go on without it
+    let ended = commands::riddle_players(&players, code).await;
+    let mut sessions = players.lock().await;
+    for (key, _) in &ended {
+        if let Some(chat_sessions) = sessions.get_mut(key) {
+            chat_sessions.end(code);
+        }
+    }
+    sessions.retain(|_, chat_sessions| !chat_sessions.is_empty());
+    drop(sessions);
+
+    let text = format!(
+        "The riddle {} was removed, your progress in it is lost",
+        riddle.current().name
+    );
+    for (key, _) in &ended {
+        // the player's chat may have removed the bot
+        if let Err(e) = send_message(&bot, key.chat, &text).await {
+            log::warn!(
+                "Cannot tell {} that riddle {} was removed: {}",
+                key,
+                code,
+                e
+            );
+        }
+    }
+
+    riddles::update_data(DialogueState::None, msg.clone(), dialogue_state_mut).await;
+    send_message(
+        &bot,
+        msg.chat.id,
+        format!(
+            "Riddle removed! {} session(s) in progress were ended.",
+            ended.len()
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn lint_riddle_code(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    riddles: riddles::Riddles,
+) -> HandlerResult {
+    let code = msg.text().unwrap();
+
+    let riddles = riddles.lock().await;
+    let Some(riddle) = riddles.get(code) else {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    };
+
+    let report = lint_report(&riddle.current().state_machine);
+    if report.is_empty() {
+        send_message(&bot, msg.chat.id, "No problems found!").await?;
+    } else {
+        send_message(&bot, msg.chat.id, format!("Problems found:{}", report)).await?;
+    }
+    riddles::update_data(DialogueState::None, msg.clone(), dialogue_state_mut).await;
+    Ok(())
+}
+
+async fn solve_riddle_code(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    riddles: riddles::Riddles,
+) -> HandlerResult {
+    let code = msg.text().unwrap();
+
+    let riddles = riddles.lock().await;
+    let Some(riddle) = riddles.get(code) else {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    };
+
+    let report = match solver::shortest_solution(&riddle.current().state_machine) {
+        Ok(solution) => format!(
+            "The riddle can be solved in {} step(s):\n\n{}",
+            solution.steps.len(),
+            solution
+        ),
+        Err(unsolvable) => format!("The riddle can't be solved: {}", unsolvable),
+    };
+    send_message(&bot, msg.chat.id, report).await?;
+    riddles::update_data(DialogueState::None, msg.clone(), dialogue_state_mut).await;
+    Ok(())
+}
+
+async fn riddle_stats_code(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    riddles: riddles::Riddles,
+) -> HandlerResult {
+    let code = msg.text().unwrap();
+
+    let riddles = riddles.lock().await;
+    let Some(riddle) = riddles.get(code) else {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    };
+
+    // states of the current version first, then the ones of older versions
+    let mut state_names: Vec<String> = riddle
+        .current()
+        .state_machine
+        .to_raw()
+        .states
+        .into_iter()
+        .map(|state| state.name)
+        .filter(|name| riddle.stats.wrong_answers.contains_key(name))
+        .collect();
+    let mut old_state_names: Vec<String> = riddle
+        .stats
+        .wrong_answers
+        .keys()
+        .filter(|name| !state_names.contains(name))
+        .cloned()
+        .collect();
+    old_state_names.sort();
+    state_names.extend(old_state_names);
+
+    let stats_string = if state_names.is_empty() {
+        "No wrong answers yet".to_owned()
+    } else {
+        state_names
+            .iter()
+            .map(|name| {
+                format!(
+                    "{}: {} wrong answer(s), out of attempts {} time(s)",
+                    name,
+                    riddle.stats.wrong_answers[name],
+                    riddle.stats.out_of_attempts.get(name).copied().unwrap_or(0)
+                )
+            })
+            .fold("Wrong answers per state:".to_owned(), |acc, s| {
+                acc + "\n" + &s
+            })
+    };
+
+    send_message(&bot, msg.chat.id, stats_string).await?;
+    riddles::update_data(DialogueState::None, msg.clone(), dialogue_state_mut).await;
+    Ok(())
+}
+
+async fn start_race_code(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    riddles: riddles::Riddles,
+    races: riddles::Races,
+) -> HandlerResult {
+    let code = msg.text().unwrap().trim().to_owned();
+
+    let Some(play_mode) = riddles
+        .lock()
+        .await
+        .get(&code)
+        .map(|riddle| riddle.current().state_machine.play_mode())
+    else {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    };
+    if play_mode == models_raw::PlayMode::Solo {
+        send_message(
+            &bot,
+            msg.chat.id,
+            "This riddle can only be played alone, teams can't race on it",
+        )
+        .await?;
+        return Ok(());
+    }
+    if races.lock().await.contains_key(&code) {
+        send_message(
+            &bot,
+            msg.chat.id,
+            "A race on this riddle is already running, see /endrace",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    send_message(
+        &bot,
+        msg.chat.id,
+        "Send the chat IDs of the teams' group chats, separated by commas. I must be a member of every chat.",
+    )
+    .await?;
+    riddles::update_data(
+        DialogueState::StartRace(StartRaceState::Teams { code }),
+        msg,
+        dialogue_state_mut,
+    )
+    .await;
+    Ok(())
+}
+
+async fn start_race_teams(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    code: String,
+) -> HandlerResult {
+    let mut chat_ids: Vec<ChatId> = vec![];
+    for id in msg.text().unwrap().split(',').map(str::trim) {
+        let Ok(id) = id.parse::<i64>() else {
+            send_message(&bot, msg.chat.id, format!("`{}` is not a chat ID", id)).await?;
+            return Ok(());
+        };
+        if !chat_ids.contains(&ChatId(id)) {
+            chat_ids.push(ChatId(id));
+        }
+    }
+    if chat_ids.len() < 2 {
+        send_message(&bot, msg.chat.id, "A race needs at least two teams").await?;
+        return Ok(());
+    }
+
+    let mut teams = vec![];
+    for chat_id in chat_ids {
+        let Ok(chat) = bot.get_chat(chat_id).await else {
+            send_message(
+                &bot,
+                msg.chat.id,
+                format!("I can't reach chat {}, add me to it first", chat_id),
+            )
+            .await?;
+            return Ok(());
+        };
+        if chat.is_private() {
+            send_message(
+                &bot,
+                msg.chat.id,
+                format!("Chat {} is not a group chat", chat_id),
+            )
+            .await?;
+            return Ok(());
+        }
+        let name = chat.title().unwrap_or("?").to_owned();
+        teams.push((chat_id, name));
+    }
+
+    send_message(
+        &bot,
+        msg.chat.id,
+        "Where should I announce the progress? Send a chat ID, or `here` for this chat.",
+    )
+    .await?;
+    riddles::update_data(
+        DialogueState::StartRace(StartRaceState::Announcements { code, teams }),
+        msg,
+        dialogue_state_mut,
+    )
+    .await;
+    Ok(())
+}
+
+/// Starts a team session of the riddle in every team's chat, all at the same
+/// moment, and announces the race.
+async fn start_race_announcements(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    (code, teams): (String, Vec<(ChatId, String)>),
+    riddles: riddles::Riddles,
+    races: riddles::Races,
+    players: ChatData<riddles::Sessions>,
+) -> HandlerResult {
+    let text = msg.text().unwrap().trim();
+    let announcements = if text == "here" {
+        msg.chat.id
+    } else if let Ok(id) = text.parse::<i64>() {
+        ChatId(id)
+    } else {
+        send_message(&bot, msg.chat.id, "Expected a chat ID or `here`").await?;
+        return Ok(());
+    };
+
+    let riddles_lock = riddles.lock().await;
+    let Some(riddle) = riddles_lock.get(&code) else {
+        send_message(&bot, msg.chat.id, "Riddle not found!").await?;
+        return Ok(());
+    };
+    let version = riddle.current();
+    let mut races_lock = races.lock().await;
+    if races_lock.contains_key(&code) {
+        send_message(
+            &bot,
+            msg.chat.id,
+            "A race on this riddle is already running, see /endrace",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let now = Utc::now();
+    let race = race::Race::new(
+        code.clone(),
+        version.name.clone(),
+        announcements,
+        teams.clone(),
+        now,
+    );
+    for (chat_id, _) in &teams {
+        let key = riddles::DataKey::chat(*chat_id);
+        let mut sessions = riddles::get_data_at(&players, key).await;
+        let mut chat_state = riddles::ChatState::new(
+            code.clone(),
+            riddle.current_number(),
+            version.state_machine.initial_state.clone(),
+            false,
+            true,
+        );
+        chat_state.started_at = now;
+        chat_state.hint_clock = now;
+        sessions.start(chat_state);
+        riddles::update_sessions(sessions, key, &players).await;
+    }
+    races_lock.insert(code, race);
+    let start_text = format!(
+        "The race is on! {} teams start at this moment, everyone in this chat plays together.\n\n{}\n\n{}",
+        teams.len(),
+        version.name,
+        version.description
+    );
+    let announcement = format!(
+        "The race on {} has started! Teams: {}",
+        version.name,
+        teams
+            .iter()
+            .map(|(_, name)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    drop(races_lock);
+    drop(riddles_lock);
+    // the race is running now, whatever happens to the messages below
+    riddles::update_data(DialogueState::None, msg.clone(), dialogue_state_mut).await;
+
+    let mut report =
+        "Race started! It ends when every team has finished, or with /endrace.".to_owned();
+    // the teams' chats may have removed the bot, the other teams still start
+    for (chat_id, name) in &teams {
+        if let Err(e) = send_message(&bot, *chat_id, &start_text).await {
+            report += &format!("\nI can't post to team {} in chat {}: {}", name, chat_id, e);
+        }
+    }
+    if let Err(e) = send_message(&bot, announcements, announcement).await {
+        report += &format!("\nI can't post to chat {}: {}", announcements, e);
+    }
+    send_message(&bot, msg.chat.id, report).await?;
+    Ok(())
+}
+
+async fn end_race_code(
+    bot: Bot,
+    msg: Message,
+    dialogue_state_mut: ChatData<DialogueState>,
+    races: riddles::Races,
+) -> HandlerResult {
+    let code = msg.text().unwrap().trim();
+
+    let Some(race) = races.lock().await.remove(code) else {
+        send_message(&bot, msg.chat.id, "No race is running on this riddle").await?;
+        return Ok(());
+    };
+
+    let standings = commands::race_standings(&race);
+    if let Err(e) = send_message(&bot, race.announcements, &standings).await {
+        log::warn!(
+            "Cannot announce the race on {} in chat {}: {}",
+            code,
+            race.announcements,
+            e
+        );
+    }
+    send_message(
+        &bot,
+        msg.chat.id,
+        format!(
+            "Race ended! Teams that haven't finished can keep playing, but their progress is no longer announced.\n\n{}",
+            standings
+        ),
+    )
+    .await?;
+    riddles::update_data(DialogueState::None, msg.clone(), dialogue_state_mut).await;
     Ok(())
 }
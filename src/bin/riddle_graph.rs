@@ -0,0 +1,52 @@
+//! Draws a riddle's state machine without going through the bot.
+//!
+//! Usage: `riddle_graph <dot|mermaid> <FILE>`, the diagram is printed to the
+//! standard output.
+
+use std::process::ExitCode;
+
+use riddle_bot::{graph, lints, models_raw, state_machine::StateMachine};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let (format, path) = match args.as_slice() {
+        [_, format, path] => match graph::Format::from_name(format) {
+            Some(format) => (format, path),
+            None => {
+                eprintln!("Unknown format `{}`, use dot or mermaid", format);
+                return ExitCode::FAILURE;
+            }
+        },
+        _ => {
+            eprintln!("Usage: riddle_graph <dot|mermaid> <FILE>");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let format_raw = models_raw::Format::detect(Some(path), &source);
+    let parsed = models_raw::parse_state_machine(&source, format_raw).and_then(|state_machine| {
+        lints::validate(&state_machine)?;
+        Ok(state_machine)
+    });
+    match parsed {
+        Ok(state_machine) => {
+            print!(
+                "{}",
+                graph::render(&StateMachine::new(state_machine), format)
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Plays a riddle in the terminal, without Telegram.
+//!
+//! Usage: `riddle_sim [--trace] FILE [--script SCRIPT]` or
+//! `riddle_sim --test FILE`
+//!
+//! `FILE` is a state machine (JSON or the text format) or a riddle exported
+//! with `/exportriddle`. Without `--script` the riddle is played
+//! interactively. With it, every line of `SCRIPT` is used as an input and the
+//! transcript is printed: inputs start with `> `, messages with `< ` and the
+//! state after every input with `= `. Running out of attempts is reported
+//! with a `! ` line, lockouts are not waited for. The exit code tells
+//! whether the script solved the riddle. `--test` runs the playthroughs
+//! embedded in the riddle instead.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+
+use async_trait::async_trait;
+use teloxide::types::ChatId;
+
+use riddle_bot::{
+    lints, models::ActionApplier, models_raw, playthroughs, state_machine::StateMachine,
+    utils::HandlerResult,
+};
+
+struct ConsoleApplier;
+
+#[async_trait]
+impl ActionApplier for ConsoleApplier {
+    async fn apply_message(&mut self, message: &str) -> HandlerResult {
+        println!("< {}", message);
+        Ok(())
+    }
+
+    async fn apply_send_to(&mut self, chat_id: ChatId, message: &str) -> HandlerResult {
+        println!("< [to chat {}] {}", chat_id, message);
+        Ok(())
+    }
+}
+
+struct Options {
+    path: String,
+    script: Option<String>,
+    trace: bool,
+    test: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Option<Options> {
+    let mut path = None;
+    let mut script = None;
+    let mut trace = false;
+    let mut test = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--script" => script = Some(args.next()?),
+            "--trace" => trace = true,
+            "--test" => test = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => return None,
+        }
+    }
+
+    Some(Options {
+        path: path?,
+        script,
+        trace,
+        test,
+    })
+}
+
+/// Loads either a bare state machine or a full riddle definition.
+fn load(path: &str) -> HandlerResult<(Option<String>, StateMachine)> {
+    let source = std::fs::read_to_string(path)?;
+    let format = models_raw::Format::detect(Some(path), &source);
+
+    if format == models_raw::Format::Json {
+        if let Ok(riddle) = serde_json::from_str::<models_raw::Riddle>(&source) {
+            let intro = format!("{}\n\n{}", riddle.name, riddle.description);
+            lints::validate(&riddle.state_machine)?;
+            return Ok((Some(intro), StateMachine::new(riddle.state_machine)));
+        }
+    }
+
+    let state_machine = models_raw::parse_state_machine(&source, format)?;
+    lints::validate(&state_machine)?;
+    Ok((None, StateMachine::new(state_machine)))
+}
+
+/// Plays the riddle with `inputs`, returns whether it was solved.
+async fn play(
+    state_machine: &StateMachine,
+    inputs: impl Iterator<Item = io::Result<String>>,
+    options: &Options,
+) -> HandlerResult<bool> {
+    let interactive = options.script.is_none();
+    let mut state = state_machine.initial_state.clone();
+    let mut attempts = HashMap::new();
+
+    let prompt = || {
+        if interactive {
+            print!("> ");
+            io::stdout().flush()
+        } else {
+            Ok(())
+        }
+    };
+
+    prompt()?;
+    for input in inputs {
+        let input = input?;
+        if !interactive {
+            println!("> {}", input);
+        }
+
+        let to = if options.trace {
+            let trace = state_machine
+                .trace(&mut ConsoleApplier, &state, &input)
+                .await?;
+            println!("{}", trace);
+            (!trace.wrong).then_some(trace.to)
+        } else {
+            state_machine
+                .apply_edge(&mut ConsoleApplier, &state, &input)
+                .await?
+        };
+
+        let answer = state_machine.count_attempt(&state, to, &mut attempts);
+        if answer.out_of_attempts {
+            if answer.lockout > 0 {
+                println!(
+                    "! out of attempts, locked out for {} second(s)",
+                    answer.lockout
+                );
+            } else {
+                println!("! out of attempts");
+            }
+        }
+        state = answer.state;
+
+        if state_machine.is_accepting(&state) {
+            println!("= {} (accepting)", state);
+            println!("You solved the riddle!");
+            return Ok(true);
+        }
+        println!("= {}", state);
+        prompt()?;
+    }
+
+    if interactive {
+        println!();
+    }
+    println!("Not solved, stopped in state {}", state);
+    Ok(false)
+}
+
+async fn test(state_machine: &StateMachine) -> HandlerResult<bool> {
+    let failures = playthroughs::run_all(state_machine).await?;
+    for failure in &failures {
+        println!("FAILED {}", failure);
+    }
+    println!(
+        "{} of {} playthrough(s) passed",
+        state_machine.tests().len() - failures.len(),
+        state_machine.tests().len()
+    );
+    Ok(failures.is_empty())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let Some(options) = parse_args(std::env::args().skip(1)) else {
+        eprintln!("Usage: riddle_sim [--trace] FILE [--script SCRIPT] | riddle_sim --test FILE");
+        return ExitCode::FAILURE;
+    };
+
+    let (intro, state_machine) = match load(&options.path) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if options.test {
+        return match test(&state_machine).await {
+            Ok(true) => ExitCode::SUCCESS,
+            Ok(false) => ExitCode::FAILURE,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if let Some(intro) = intro {
+        println!("{}\n", intro);
+    }
+
+    let result = match options.script.as_deref() {
+        Some(script) => match std::fs::File::open(script) {
+            Ok(file) => play(&state_machine, io::BufReader::new(file).lines(), &options).await,
+            Err(e) => Err(e.into()),
+        },
+        None => play(&state_machine, io::stdin().lock().lines(), &options).await,
+    };
+
+    match result {
+        // a script that doesn't solve the riddle fails, so riddles can be
+        // checked with scripts
+        Ok(false) if options.script.is_some() => ExitCode::FAILURE,
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Option<Options> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn arguments() {
+        let options = args(&["--trace", "riddle.txt", "--script", "inputs"]).unwrap();
+        assert_eq!(options.path, "riddle.txt");
+        assert_eq!(options.script.as_deref(), Some("inputs"));
+        assert!(options.trace);
+        assert!(!options.test);
+
+        assert!(args(&[]).is_none());
+        assert!(args(&["a", "b"]).is_none());
+        assert!(args(&["a", "--script"]).is_none());
+    }
+
+    #[tokio::test]
+    async fn script() {
+        let state_machine = StateMachine::new(
+            riddle_bot::dsl::parse("initial a\naccept c\na --\"x\"--> b\nb --\"y\"--> c").unwrap(),
+        );
+        let options = args(&["riddle.txt", "--script", "inputs"]).unwrap();
+        let inputs = |inputs: &[&str]| {
+            inputs
+                .iter()
+                .map(|input| Ok(input.to_string()))
+                .collect::<Vec<_>>()
+                .into_iter()
+        };
+
+        assert!(play(&state_machine, inputs(&["x", "z", "y"]), &options)
+            .await
+            .unwrap());
+        assert!(!play(&state_machine, inputs(&["x", "z"]), &options)
+            .await
+            .unwrap());
+    }
+}
@@ -1,10 +1,24 @@
+use std::collections::HashMap;
+
 use async_trait::async_trait;
-use teloxide::{dispatching::UpdateHandler, prelude::*, utils::command::BotCommands};
+use chrono::{Duration, Utc};
+use teloxide::{
+    dispatching::UpdateHandler,
+    prelude::*,
+    types::{Chat, InlineKeyboardButton, InlineKeyboardMarkup},
+    utils::command::BotCommands,
+};
 
 use crate::{
+    admin_commands,
+    leaderboard::{self, Ranking},
     models,
-    riddles::{self, update_data, ChatData, ChatState},
-    utils::{send_message, Error, HandlerResult},
+    models_raw::{self, PlayMode},
+    race::{self, Progress, Race},
+    riddles::{self, update_data, ChatData, ChatState, DataKey, Sessions, UndoError},
+    state_machine::StateMachine,
+    throttling::{self, RateLimits},
+    utils::{format_duration, send_message, Error, HandlerResult},
 };
 
 #[derive(BotCommands, Clone)]
@@ -15,30 +29,114 @@ use crate::{
 pub(crate) enum Command {
     #[command(description = "display this text.")]
     Help,
-    #[command(description = "display this text.")]
-    Start,
-    #[command(description = "start the riddle.")]
-    StartRiddle,
+    #[command(description = "display this text, or start the riddle from a link.")]
+    Start(String),
+    #[command(description = "start the riddle: /startriddle [CODE].")]
+    StartRiddle(String),
+    #[command(description = "play a riddle together with this group: /teamriddle [CODE].")]
+    TeamRiddle(String),
+    #[command(description = "browse the public riddles, optionally by tag: /browse [TAG].")]
+    Browse(String),
     #[command(description = "stop the current riddle.")]
     StopRiddle,
+    #[command(description = "list the riddles you are playing.")]
+    Riddles,
+    #[command(description = "switch to another riddle you are playing.")]
+    Switch,
+    #[command(description = "pause the current riddle, keeping your progress.")]
+    Pause,
+    #[command(description = "continue a paused riddle: /resume CODE.")]
+    Resume(String),
+    #[command(description = "start the current riddle over.")]
+    Restart,
+    #[command(description = "take back your last step, if the riddle allows it.")]
+    Undo,
+    #[command(description = "show your answers in the current riddle.")]
+    Transcript,
+    #[command(description = "show who solved a riddle: /leaderboard CODE [time|score].")]
+    Leaderboard(String),
+    #[command(description = "playtest your own riddle, showing every transition.")]
+    TestRiddle,
+    #[command(description = "show the next hint for the current step of the riddle.")]
+    Hint,
+    #[command(description = "show your progress in the current riddle.")]
+    Status,
+}
+
+/// How a riddle is started.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Play {
+    Solo,
+    // by a group chat together
+    Team,
+    // a playtest, see /testriddle
+    Test,
+}
+
+impl Play {
+    /// Riddles started without saying how, e.g. from a link or the catalog,
+    /// are played as a team in groups.
+    fn default_for(chat: &Chat) -> Self {
+        if chat.is_private() {
+            Play::Solo
+        } else {
+            Play::Team
+        }
+    }
 }
 
 #[derive(Clone, Default, Debug)]
-enum DialogueState {
+pub(crate) enum DialogueState {
     #[default]
     None,
-    StartRiddle,
-    Riddle(ChatState),
+    // waiting for the code of the riddle to start
+    StartRiddle(Play),
+    Switch,
+    Resume,
+    Leaderboard,
+}
+
+const DEFAULT_SESSION_EXPIRY_HOURS: i64 = 7 * 24;
+// how often expired sessions are looked for
+const EXPIRY_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+fn session_expiry_from_env() -> Duration {
+    let hours = std::env::var("SESSION_EXPIRY_HOURS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|hours| *hours > 0)
+        .unwrap_or(DEFAULT_SESSION_EXPIRY_HOURS);
+    Duration::hours(hours)
 }
 
 pub(crate) fn dependencies() -> DependencyMap {
-    dptree::deps![ChatData::<DialogueState>::default()]
+    let sessions = ChatData::<Sessions>::default();
+    tokio::spawn(expire_sessions(sessions.clone(), session_expiry_from_env()));
+    dptree::deps![ChatData::<DialogueState>::default(), sessions]
+}
+
+/// Removes the sessions that were not active for `expiry`, running or
+/// paused, so that abandoned riddles don't pile up.
+async fn expire_sessions(sessions: ChatData<Sessions>, expiry: Duration) {
+    let mut interval = tokio::time::interval(EXPIRY_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let idle_since = Utc::now() - expiry;
+
+        let mut sessions = sessions.lock().await;
+        for (key, chat_sessions) in sessions.iter_mut() {
+            for expired in chat_sessions.expire(idle_since) {
+                log::info!("Session of riddle {} of {} expired", expired.riddle, key);
+            }
+        }
+        sessions.retain(|_, chat_sessions| !chat_sessions.is_empty());
+    }
 }
 
 pub(crate) fn schema() -> UpdateHandler<Error> {
     use dptree::case;
 
-    Update::filter_message()
+    let messages = Update::filter_message()
         .map_async(riddles::get_data::<DialogueState>)
         .branch(
             dptree::entry()
@@ -49,45 +147,282 @@ pub(crate) fn schema() -> UpdateHandler<Error> {
                         .endpoint(command_help),
                 )
                 .branch(
-                    case![Command::Start]
+                    case![Command::Start(payload)]
                         .inspect_async(riddles::update_data_func(DialogueState::None))
-                        .endpoint(command_help),
+                        .endpoint(command_start),
+                )
+                .branch(
+                    case![Command::StartRiddle(code)]
+                        .map(|code: String| (code, Play::Solo))
+                        .endpoint(command_start_riddle_inline),
+                )
+                .branch(
+                    case![Command::TeamRiddle(code)]
+                        .map(|code: String| (code, Play::Team))
+                        .endpoint(command_start_riddle_inline),
                 )
                 .branch(
-                    case![Command::StartRiddle]
-                        .inspect_async(riddles::update_data_func(DialogueState::StartRiddle))
+                    case![Command::TestRiddle]
+                        .inspect_async(riddles::update_data_func(DialogueState::StartRiddle(
+                            Play::Test,
+                        )))
                         .endpoint(command_start_riddle),
                 )
-                .branch(case![Command::StopRiddle].endpoint(command_stop_riddle)),
+                .branch(
+                    case![Command::StopRiddle]
+                        .inspect_async(riddles::update_data_func(DialogueState::None))
+                        .endpoint(command_stop_riddle),
+                )
+                .branch(
+                    case![Command::Riddles]
+                        .inspect_async(riddles::update_data_func(DialogueState::None))
+                        .endpoint(command_riddles),
+                )
+                .branch(
+                    case![Command::Switch]
+                        .inspect_async(riddles::update_data_func(DialogueState::Switch))
+                        .endpoint(command_switch),
+                )
+                .branch(
+                    case![Command::Pause]
+                        .inspect_async(riddles::update_data_func(DialogueState::None))
+                        .endpoint(command_pause),
+                )
+                .branch(case![Command::Resume(code)].endpoint(command_resume))
+                .branch(
+                    case![Command::Restart]
+                        .inspect_async(riddles::update_data_func(DialogueState::None))
+                        .endpoint(command_restart),
+                )
+                .branch(
+                    case![Command::Undo]
+                        .inspect_async(riddles::update_data_func(DialogueState::None))
+                        .endpoint(command_undo),
+                )
+                .branch(
+                    case![Command::Browse(tag)]
+                        .inspect_async(riddles::update_data_func(DialogueState::None))
+                        .endpoint(command_browse),
+                )
+                .branch(case![Command::Transcript].endpoint(command_transcript))
+                .branch(case![Command::Leaderboard(args)].endpoint(command_leaderboard))
+                .branch(case![Command::Hint].endpoint(command_hint))
+                .branch(case![Command::Status].endpoint(command_status)),
         )
         .branch(
             dptree::entry()
-                .branch(case![DialogueState::StartRiddle].endpoint(command_start_riddle_code))
-                .branch(case![DialogueState::Riddle(state)].endpoint(command_riddle)),
-        )
+                .branch(case![DialogueState::StartRiddle(play)].endpoint(command_start_riddle_code))
+                .branch(case![DialogueState::Switch].endpoint(command_switch_code))
+                .branch(case![DialogueState::Resume].endpoint(command_resume_code))
+                .branch(case![DialogueState::Leaderboard].endpoint(command_leaderboard_code))
+                .branch(case![DialogueState::None].endpoint(command_riddle)),
+        );
+
+    dptree::entry()
+        .branch(messages)
+        .branch(Update::filter_callback_query().endpoint(catalog_callback))
+}
+
+/// Players in the middle of the given riddle, with their progress.
+pub(crate) async fn riddle_players(
+    sessions: &ChatData<Sessions>,
+    code: &str,
+) -> Vec<(DataKey, ChatState)> {
+    sessions
+        .lock()
+        .await
+        .iter()
+        .filter_map(|(key, sessions)| {
+            sessions
+                .get(code)
+                .map(|chat_state| (*key, chat_state.clone()))
+        })
+        .collect()
+}
+
+/// Moves the players of the riddle who are in a state that `state_machine`,
+/// version `version` of the riddle, doesn't have to its state `to`, or stops
+/// their riddle if there is no such state. Moved players continue on that
+/// version and can't undo past the move. Returns who was affected.
+pub(crate) async fn move_players(
+    sessions: &ChatData<Sessions>,
+    code: &str,
+    state_machine: &StateMachine,
+    version: usize,
+    to: Option<&str>,
+) -> Vec<DataKey> {
+    let mut sessions = sessions.lock().await;
+    let mut moved = vec![];
+    for (key, chat_sessions) in sessions.iter_mut() {
+        let Some(mut chat_state) = chat_sessions
+            .get(code)
+            .filter(|chat_state| !state_machine.has_state(&chat_state.state))
+            .cloned()
+        else {
+            continue;
+        };
+
+        match to {
+            Some(to) => {
+                chat_state.version = version;
+                chat_state.history.clear();
+                chat_state.move_to(to.to_owned());
+                chat_sessions.update(chat_state);
+            }
+            None => {
+                chat_sessions.end(code);
+            }
+        }
+        moved.push(*key);
+    }
+    sessions.retain(|_, chat_sessions| !chat_sessions.is_empty());
+    moved
+}
+
+/// Who answered how many times in a team session, the most active first.
+fn members_summary(chat_state: &ChatState) -> String {
+    let mut members: Vec<&riddles::Member> = chat_state.members.values().collect();
+    members.sort_by(|a, b| b.answers.cmp(&a.answers).then(a.name.cmp(&b.name)));
+    let members: Vec<String> = members
+        .iter()
+        .map(|member| {
+            format!(
+                "{} {} ({} wrong)",
+                member.name, member.answers, member.wrong_answers
+            )
+        })
+        .collect();
+    format!("Answers by member: {}", members.join(", "))
+}
+
+/// The riddle's name and code, as shown in lists of sessions.
+fn session_title(riddles: &HashMap<String, riddles::Riddle>, chat_state: &ChatState) -> String {
+    let name = riddles
+        .get(&chat_state.riddle)
+        .and_then(|riddle| riddle.version(chat_state.version))
+        .map_or("?", |version| version.name.as_str());
+    format!(
+        "{} (`{}`{})",
+        name,
+        chat_state.riddle,
+        if chat_state.testing {
+            ", playtest"
+        } else if chat_state.team {
+            ", team"
+        } else {
+            ""
+        }
+    )
+}
+
+/// Ends the session of a riddle that was removed while it was played, see
+/// `admin_commands::remove_riddle_code`.
+async fn end_removed_riddle(
+    bot: &Bot,
+    msg: &Message,
+    key: DataKey,
+    mut sessions: Sessions,
+    code: &str,
+    riddles: &riddles::Riddles,
+    sessions_mut: &ChatData<Sessions>,
+) -> HandlerResult {
+    let mut text = "This riddle was removed".to_owned();
+    if let Some(next) = sessions.end(code) {
+        text += &format!(
+            ". Your answers go to {} now.",
+            session_title(&*riddles.lock().await, next)
+        );
+    }
+    reply(bot, msg, key, text).await?;
+    riddles::update_sessions(sessions, key, sessions_mut).await;
+    Ok(())
+}
+
+/// Who the replies about the sessions at `key` are for, if they belong to a
+/// member of a group chat rather than the whole chat.
+fn addressee(msg: &Message, key: DataKey) -> Option<String> {
+    key.user?;
+    msg.from()
+        .map(|user| user.mention().unwrap_or_else(|| user.full_name()))
+}
+
+/// Sends `text` to the chat of `msg`, naming the player it is for if they
+/// play on their own in a group chat.
+async fn reply<T>(bot: &Bot, msg: &Message, key: DataKey, text: T) -> HandlerResult
+where
+    T: Into<String>,
+{
+    let text = match addressee(msg, key) {
+        Some(addressee) => format!("{}: {}", addressee, text.into()),
+        None => text.into(),
+    };
+    send_message(bot, msg.chat.id, text).await
 }
 
 struct Applier<'a> {
     bot: &'a Bot,
     chat_id: ChatId,
+    // prepended to the riddle's messages, see `addressee`
+    addressee: Option<String>,
 }
 
 impl<'a> Applier<'a> {
-    fn new(bot: &'a Bot, chat_id: ChatId) -> Self {
-        Self { bot, chat_id }
+    fn new(bot: &'a Bot, chat_id: ChatId, addressee: Option<String>) -> Self {
+        Self {
+            bot,
+            chat_id,
+            addressee,
+        }
     }
 }
 
 #[async_trait]
 impl models::ActionApplier for Applier<'_> {
     async fn apply_message(&mut self, message: &str) -> HandlerResult {
-        send_message(&self.bot, self.chat_id, message).await?;
+        match &self.addressee {
+            Some(addressee) => {
+                send_message(
+                    self.bot,
+                    self.chat_id,
+                    format!("{}: {}", addressee, message),
+                )
+                .await?
+            }
+            None => send_message(self.bot, self.chat_id, message).await?,
+        }
+
+        Ok(())
+    }
+
+    async fn apply_send_to(&mut self, chat_id: ChatId, message: &str) -> HandlerResult {
+        send_message(self.bot, chat_id, message).await?;
+
+        Ok(())
+    }
+}
+
+/// Used in playtests: messages for other chats are shown to the tester
+/// instead.
+struct SandboxApplier<'a> {
+    bot: &'a Bot,
+    chat_id: ChatId,
+}
+
+#[async_trait]
+impl models::ActionApplier for SandboxApplier<'_> {
+    async fn apply_message(&mut self, message: &str) -> HandlerResult {
+        send_message(self.bot, self.chat_id, message).await?;
 
         Ok(())
     }
 
     async fn apply_send_to(&mut self, chat_id: ChatId, message: &str) -> HandlerResult {
-        send_message(&self.bot, chat_id, message).await?;
+        send_message(
+            self.bot,
+            self.chat_id,
+            format!("[would be sent to chat {}]\n{}", chat_id, message),
+        )
+        .await?;
 
         Ok(())
     }
@@ -98,110 +433,1273 @@ async fn command_help(bot: Bot, msg: Message) -> HandlerResult {
     Ok(())
 }
 
+/// `/start` with a payload comes from a link like `t.me/bot?start=CODE` and
+/// starts that riddle.
+async fn command_start(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    sessions: ChatData<Sessions>,
+    rate_limits: RateLimits,
+    payload: String,
+) -> HandlerResult {
+    let code = payload.trim();
+    if code.is_empty() {
+        return command_help(bot, msg).await;
+    }
+    let play = Play::default_for(&msg.chat);
+    start_riddle(bot, msg, riddles, sessions, rate_limits, code, play).await?;
+    Ok(())
+}
+
 async fn command_start_riddle(bot: Bot, msg: Message) -> HandlerResult {
     send_message(&bot, msg.chat.id, "What is the code of the riddle?").await?;
     Ok(())
 }
 
+async fn command_start_riddle_inline(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    states: ChatData<DialogueState>,
+    sessions: ChatData<Sessions>,
+    rate_limits: RateLimits,
+    (code, play): (String, Play),
+) -> HandlerResult {
+    let code = code.trim();
+    if code.is_empty() {
+        update_data(DialogueState::StartRiddle(play), msg.clone(), states).await;
+        return command_start_riddle(bot, msg).await;
+    }
+
+    update_data(DialogueState::None, msg.clone(), states).await;
+    start_riddle(bot, msg, riddles, sessions, rate_limits, code, play).await?;
+    Ok(())
+}
+
 async fn command_start_riddle_code(
     bot: Bot,
     msg: Message,
     riddles: riddles::Riddles,
     states: ChatData<DialogueState>,
+    sessions: ChatData<Sessions>,
+    rate_limits: RateLimits,
+    play: Play,
 ) -> HandlerResult {
-    let code = msg.text().unwrap();
+    let Some(code) = msg.text().map(|text| text.trim().to_owned()) else {
+        return Ok(());
+    };
+    if start_riddle(
+        bot,
+        msg.clone(),
+        riddles,
+        sessions,
+        rate_limits,
+        &code,
+        play,
+    )
+    .await?
+    {
+        update_data(DialogueState::None, msg, states).await;
+    }
+    Ok(())
+}
+
+/// Starts the riddle if the player may play it. Returns whether it was
+/// started.
+async fn start_riddle(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    sessions_mut: ChatData<Sessions>,
+    rate_limits: RateLimits,
+    code: &str,
+    play: Play,
+) -> HandlerResult<bool> {
+    if !throttling::allow(&bot, &msg, &rate_limits, throttling::Request::Code).await? {
+        return Ok(false);
+    }
 
     let riddles_lock = riddles.lock().await;
 
-    let riddle_opt = riddles_lock.get(code);
+    // drafts and playtests are only for the riddle's creator and admins
+    let riddle_opt = riddles_lock.get(code).filter(|riddle| {
+        (riddle.published && play != Play::Test)
+            || msg
+                .from()
+                .is_some_and(|user| user.id == riddle.creator || admin_commands::is_admin(user.id))
+    });
 
-    match riddle_opt {
-        None => {
-            send_message(&bot, msg.chat.id, "Riddle not found").await?;
+    let Some(riddle) = riddle_opt else {
+        send_message(&bot, msg.chat.id, "Riddle not found").await?;
+        return Ok(false);
+    };
+    let player = DataKey::member(&msg);
+    enter_riddle(&bot, msg, player, sessions_mut, code, riddle, play).await
+}
+
+/// Why the riddle can't be played this way in the chat, if it can't.
+fn play_error(chat: &Chat, riddle: &riddles::Riddle, play: Play) -> Option<&'static str> {
+    match (play, riddle.current().state_machine.play_mode()) {
+        (Play::Team, _) if chat.is_private() => Some(
+            "Team riddles are played in group chats: add me to a group and use /teamriddle there",
+        ),
+        (Play::Solo, PlayMode::Team) => {
+            Some("This riddle can only be played as a team: use /teamriddle in a group")
+        }
+        (Play::Team, PlayMode::Solo) => {
+            Some("This riddle can only be played alone: use /startriddle")
         }
-        Some(riddle) => {
-            let chat_id = msg.chat.id;
+        _ => None,
+    }
+}
 
-            update_data(
-                DialogueState::Riddle(ChatState {
-                    riddle: code.to_owned(),
-                    state: riddle.state_machine.initial_state.clone(),
-                }),
-                msg,
-                states,
-            )
-            .await;
+/// Starts a session of the riddle for `player`, or switches to it if they are
+/// already playing the riddle. `player` is the sender of `msg`, unless `msg`
+/// is the bot's own message whose button they pressed. Team riddles belong
+/// to the whole chat, other riddles started in a group chat to the member who
+/// started them. Returns whether the riddle is being played now.
+async fn enter_riddle(
+    bot: &Bot,
+    msg: Message,
+    player: DataKey,
+    sessions_mut: ChatData<Sessions>,
+    code: &str,
+    riddle: &riddles::Riddle,
+    play: Play,
+) -> HandlerResult<bool> {
+    let key = match play {
+        Play::Team => DataKey::chat(msg.chat.id),
+        Play::Solo | Play::Test => player,
+    };
+    let version = riddle.current();
+    let mut sessions = riddles::get_data_at(&sessions_mut, key).await;
 
-            send_message(&bot, chat_id, "Let's get started!").await?;
+    // starting a riddle again doesn't throw away the progress
+    if sessions.resume(code, Utc::now()) {
+        reply(
+            bot,
+            &msg,
+            key,
+            "You are already playing this riddle, your answers go to it now. Use /stopriddle to start over.",
+        )
+        .await?;
+        riddles::update_sessions(sessions, key, &sessions_mut).await;
+        riddles::route_to(&sessions_mut, player, key).await;
+        return Ok(true);
+    }
 
-            send_message(
-                &bot,
-                chat_id,
-                format!("{}\n\n{}", riddle.name, riddle.description),
-            )
-            .await?;
+    if let Some(error) = play_error(&msg.chat, riddle, play) {
+        reply(bot, &msg, key, error).await?;
+        return Ok(false);
+    }
+
+    sessions.start(ChatState::new(
+        code.to_owned(),
+        riddle.current_number(),
+        version.state_machine.initial_state.clone(),
+        play == Play::Test,
+        play == Play::Team,
+    ));
+    riddles::update_sessions(sessions, key, &sessions_mut).await;
+    riddles::route_to(&sessions_mut, player, key).await;
+
+    let text = match play {
+        Play::Solo if key.user.is_some() => {
+            "Let's get started! You play on your own, your messages in this chat go to your riddle until you finish or stop it."
+        }
+        Play::Solo => "Let's get started!",
+        Play::Team => {
+            "Let's get started! Everyone in this chat plays together, every answer is credited to whoever sent it."
+        }
+        Play::Test => {
+            "Playtest started! Every answer shows the transition it caused, messages for other chats are sent to you."
         }
+    };
+    reply(bot, &msg, key, text).await?;
+
+    reply(
+        bot,
+        &msg,
+        key,
+        format!("{}\n\n{}", version.name, version.description),
+    )
+    .await?;
+    Ok(true)
+}
+
+// riddles per page of the catalog
+const CATALOG_PAGE_SIZE: usize = 5;
+// Telegram rejects a button with longer callback data, and the whole keyboard
+// with it
+const MAX_CALLBACK_DATA_LENGTH: usize = 64;
+
+/// The callback data of the catalog's start button for the riddle, if its
+/// code fits in it.
+pub(crate) fn start_callback(code: &str) -> Option<String> {
+    let data = format!("start:{}", code);
+    (data.len() <= MAX_CALLBACK_DATA_LENGTH).then_some(data)
+}
+
+/// A page of the public catalog, only the riddles tagged `tag` unless it is
+/// empty. Every riddle gets a start button, the last row turns the pages.
+fn catalog_page(
+    riddles: &HashMap<String, riddles::Riddle>,
+    tag: &str,
+    page: usize,
+) -> (String, InlineKeyboardMarkup) {
+    let mut listed: Vec<(&String, &riddles::Riddle, &riddles::Listing)> = riddles
+        .iter()
+        .filter(|(code, riddle)| riddle.published && start_callback(code).is_some())
+        .filter_map(|(code, riddle)| {
+            riddle
+                .listing
+                .as_ref()
+                .filter(|listing| tag.is_empty() || listing.tags.iter().any(|t| t == tag))
+                .map(|listing| (code, riddle, listing))
+        })
+        .collect();
+    listed.sort_by_key(|(code, riddle, _)| (riddle.current().name.to_lowercase(), *code));
+
+    if listed.is_empty() {
+        let text = if tag.is_empty() {
+            "No riddles are listed yet".to_owned()
+        } else {
+            format!("No riddles are tagged {}", tag)
+        };
+        return (text, InlineKeyboardMarkup::default());
     }
 
+    let pages = listed.len().div_ceil(CATALOG_PAGE_SIZE);
+    let page = page.min(pages - 1);
+    let mut text = format!(
+        "Riddles{} (page {} of {}):",
+        if tag.is_empty() {
+            String::new()
+        } else {
+            format!(" tagged {}", tag)
+        },
+        page + 1,
+        pages
+    );
+    let mut rows = vec![];
+    for (code, riddle, listing) in listed
+        .iter()
+        .skip(page * CATALOG_PAGE_SIZE)
+        .take(CATALOG_PAGE_SIZE)
+    {
+        let name = &riddle.current().name;
+        text += &format!("\n\n{} ({})", name, listing.difficulty);
+        if !listing.tags.is_empty() {
+            text += &format!("\nTags: {}", listing.tags.join(", "));
+        }
+        text += &format!("\n{}", listing.blurb);
+        if let Some(data) = start_callback(code) {
+            rows.push(vec![InlineKeyboardButton::callback(
+                format!("Start: {}", name),
+                data,
+            )]);
+        }
+    }
+
+    let mut navigation = vec![];
+    if page > 0 {
+        navigation.push(InlineKeyboardButton::callback(
+            "« Previous",
+            format!("browse:{}:{}", page - 1, tag),
+        ));
+    }
+    if page + 1 < pages {
+        navigation.push(InlineKeyboardButton::callback(
+            "Next »",
+            format!("browse:{}:{}", page + 1, tag),
+        ));
+    }
+    if !navigation.is_empty() {
+        rows.push(navigation);
+    }
+
+    (text, InlineKeyboardMarkup::new(rows))
+}
+
+async fn command_browse(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    tag: String,
+) -> HandlerResult {
+    let tag = tag.trim().to_lowercase();
+    let (text, keyboard) = catalog_page(&*riddles.lock().await, &tag, 0);
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
+
+/// Handles the buttons of the catalog: `browse:PAGE:TAG` turns the page,
+/// `start:CODE` starts the riddle.
+async fn catalog_callback(
+    bot: Bot,
+    query: CallbackQuery,
+    riddles: riddles::Riddles,
+    states: ChatData<DialogueState>,
+    sessions_mut: ChatData<Sessions>,
+) -> HandlerResult {
+    bot.answer_callback_query(query.id).await?;
+    // the message with the buttons is missing if it is too old
+    let (Some(data), Some(msg)) = (query.data, query.message) else {
+        return Ok(());
+    };
+
+    if let Some(code) = data.strip_prefix("start:") {
+        let riddles_lock = riddles.lock().await;
+        let Some(riddle) = riddles_lock
+            .get(code)
+            .filter(|riddle| riddle.published && riddle.listing.is_some())
+        else {
+            send_message(&bot, msg.chat.id, "This riddle is no longer in the catalog").await?;
+            return Ok(());
+        };
+        // the message with the buttons is the bot's, the player is who
+        // pressed the button
+        let player = DataKey {
+            chat: msg.chat.id,
+            user: (!msg.chat.is_private()).then_some(query.from.id),
+        };
+        riddles::update_data_at(DialogueState::None, player, &states).await;
+        let play = Play::default_for(&msg.chat);
+        enter_riddle(&bot, msg, player, sessions_mut, code, riddle, play).await?;
+    } else if let Some(args) = data.strip_prefix("browse:") {
+        let (page, tag) = args.split_once(':').unwrap_or((args, ""));
+        let (text, keyboard) = catalog_page(&*riddles.lock().await, tag, page.parse().unwrap_or(0));
+        bot.edit_message_text(msg.chat.id, msg.id, text)
+            .reply_markup(keyboard)
+            .await?;
+    }
     Ok(())
 }
 
 async fn command_stop_riddle(
     bot: Bot,
     msg: Message,
-    state: DialogueState,
+    riddles: riddles::Riddles,
+    sessions_mut: ChatData<Sessions>,
+) -> HandlerResult {
+    let key = riddles::sessions_key(&sessions_mut, &msg).await;
+    let mut sessions = riddles::get_data_at(&sessions_mut, key).await;
+    let Some(code) = sessions
+        .focused()
+        .map(|chat_state| chat_state.riddle.clone())
+    else {
+        reply(&bot, &msg, key, "No riddle is running").await?;
+        return Ok(());
+    };
+
+    let text = match sessions.end(&code) {
+        Some(next) => format!(
+            "Riddle stopped. Your answers go to {} now.",
+            session_title(&*riddles.lock().await, next)
+        ),
+        None => "Riddle stopped".to_owned(),
+    };
+    reply(&bot, &msg, key, text).await?;
+    riddles::update_sessions(sessions, key, &sessions_mut).await;
+    Ok(())
+}
+
+async fn command_riddles(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    sessions_mut: ChatData<Sessions>,
+) -> HandlerResult {
+    let key = riddles::sessions_key(&sessions_mut, &msg).await;
+    // a group member's own riddles are listed along with the chat's
+    let member = DataKey::member(&msg);
+    let mut keys = vec![member];
+    if member.user.is_some() {
+        keys.push(DataKey::chat(msg.chat.id));
+    }
+
+    let mut text = String::new();
+    for sessions_key in keys {
+        let sessions = riddles::get_data_at(&sessions_mut, sessions_key).await;
+        let riddles = riddles.lock().await;
+        for chat_state in sessions.iter() {
+            let mark = if sessions_key == key && sessions.is_focused(&chat_state.riddle) {
+                " <- your answers go here"
+            } else if chat_state.is_paused() {
+                " (paused)"
+            } else {
+                ""
+            };
+            text += &format!("\n{}{}", session_title(&riddles, chat_state), mark);
+        }
+    }
+
+    if text.is_empty() {
+        reply(&bot, &msg, key, "You are not playing any riddle").await?;
+    } else {
+        reply(&bot, &msg, key, format!("Your riddles:{}", text)).await?;
+    }
+    Ok(())
+}
+
+async fn command_switch(bot: Bot, msg: Message) -> HandlerResult {
+    send_message(
+        &bot,
+        msg.chat.id,
+        "What is the code of the riddle? (see /riddles)",
+    )
+    .await?;
+    Ok(())
+}
+
+async fn command_switch_code(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
     states: ChatData<DialogueState>,
+    sessions_mut: ChatData<Sessions>,
 ) -> HandlerResult {
-    match state {
-        DialogueState::None => {
-            send_message(&bot, msg.chat.id, "No riddle is running").await?;
+    let Some(code) = msg.text().map(str::trim) else {
+        return Ok(());
+    };
+
+    let key = riddles::sessions_key_for(&sessions_mut, &msg, code).await;
+    let mut sessions = riddles::get_data_at(&sessions_mut, key).await;
+    if sessions.get(code).is_some_and(ChatState::is_paused) {
+        reply(
+            &bot,
+            &msg,
+            key,
+            format!("This riddle is paused, use /resume {} to continue it", code),
+        )
+        .await?;
+        return Ok(());
+    }
+    if !sessions.focus(code) {
+        reply(&bot, &msg, key, "You are not playing this riddle").await?;
+        return Ok(());
+    }
+
+    let title = session_title(&*riddles.lock().await, sessions.focused().unwrap());
+    reply(&bot, &msg, key, format!("Your answers go to {} now", title)).await?;
+    update_data(DialogueState::None, msg.clone(), states).await;
+    riddles::update_sessions(sessions, key, &sessions_mut).await;
+    riddles::route_to(&sessions_mut, DataKey::member(&msg), key).await;
+    Ok(())
+}
+
+async fn command_pause(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    sessions_mut: ChatData<Sessions>,
+) -> HandlerResult {
+    let key = riddles::sessions_key(&sessions_mut, &msg).await;
+    let mut sessions = riddles::get_data_at(&sessions_mut, key).await;
+    let Some(code) = sessions
+        .focused()
+        .map(|chat_state| chat_state.riddle.clone())
+    else {
+        reply(&bot, &msg, key, "No riddle is running").await?;
+        return Ok(());
+    };
+
+    let mut text = format!(
+        "Riddle paused, your progress is kept. Use /resume {} to continue it.",
+        code
+    );
+    if let Some(next) = sessions.pause(&code, Utc::now()) {
+        text += &format!(
+            "\nYour answers go to {} now.",
+            session_title(&*riddles.lock().await, next)
+        );
+    }
+    reply(&bot, &msg, key, text).await?;
+    riddles::update_sessions(sessions, key, &sessions_mut).await;
+    Ok(())
+}
+
+async fn command_resume(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    states: ChatData<DialogueState>,
+    sessions_mut: ChatData<Sessions>,
+    code: String,
+) -> HandlerResult {
+    if code.trim().is_empty() {
+        update_data(DialogueState::Resume, msg.clone(), states).await;
+        send_message(
+            &bot,
+            msg.chat.id,
+            "What is the code of the riddle? (see /riddles)",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    update_data(DialogueState::None, msg.clone(), states).await;
+    resume_riddle(bot, msg, riddles, sessions_mut, code.trim()).await
+}
+
+async fn command_resume_code(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    states: ChatData<DialogueState>,
+    sessions_mut: ChatData<Sessions>,
+) -> HandlerResult {
+    let Some(code) = msg.text().map(|text| text.trim().to_owned()) else {
+        return Ok(());
+    };
+    update_data(DialogueState::None, msg.clone(), states).await;
+    resume_riddle(bot, msg, riddles, sessions_mut, &code).await
+}
+
+async fn resume_riddle(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    sessions_mut: ChatData<Sessions>,
+    code: &str,
+) -> HandlerResult {
+    let key = riddles::sessions_key_for(&sessions_mut, &msg, code).await;
+    let mut sessions = riddles::get_data_at(&sessions_mut, key).await;
+    if !sessions.resume(code, Utc::now()) {
+        reply(
+            &bot,
+            &msg,
+            key,
+            "You are not playing this riddle (abandoned riddles expire after a while)",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let riddles_lock = riddles.lock().await;
+    let chat_state = sessions.focused().unwrap();
+    let mut text = format!(
+        "Welcome back! Your answers go to {} now.",
+        session_title(&riddles_lock, chat_state)
+    );
+    if let Some(description) = riddles_lock
+        .get(&chat_state.riddle)
+        .and_then(|riddle| riddle.version(chat_state.version))
+        .and_then(|version| version.state_machine.description(&chat_state.state))
+    {
+        text += &format!("\n\n{}", description);
+    }
+    drop(riddles_lock);
+
+    reply(&bot, &msg, key, text).await?;
+    riddles::update_sessions(sessions, key, &sessions_mut).await;
+    riddles::route_to(&sessions_mut, DataKey::member(&msg), key).await;
+    Ok(())
+}
+
+async fn command_restart(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    sessions_mut: ChatData<Sessions>,
+) -> HandlerResult {
+    let key = riddles::sessions_key(&sessions_mut, &msg).await;
+    let mut sessions = riddles::get_data_at(&sessions_mut, key).await;
+    let Some(chat_state) = sessions.focused() else {
+        reply(&bot, &msg, key, "No riddle is running").await?;
+        return Ok(());
+    };
+
+    let riddles_lock = riddles.lock().await;
+    let Some(version) = riddles_lock
+        .get(&chat_state.riddle)
+        .and_then(|riddle| riddle.version(chat_state.version))
+    else {
+        drop(riddles_lock);
+        let code = chat_state.riddle.clone();
+        return end_removed_riddle(&bot, &msg, key, sessions, &code, &riddles, &sessions_mut).await;
+    };
+    let restarted = chat_state.restart(version.state_machine.initial_state.clone());
+    let text = format!(
+        "Starting over!\n\n{}\n\n{}",
+        version.name, version.description
+    );
+    drop(riddles_lock);
+
+    sessions.update(restarted);
+    reply(&bot, &msg, key, text).await?;
+    riddles::update_sessions(sessions, key, &sessions_mut).await;
+    Ok(())
+}
+
+async fn command_undo(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    sessions_mut: ChatData<Sessions>,
+) -> HandlerResult {
+    let key = riddles::sessions_key(&sessions_mut, &msg).await;
+    let mut sessions = riddles::get_data_at(&sessions_mut, key).await;
+    let Some(mut chat_state) = sessions.focused().cloned() else {
+        reply(&bot, &msg, key, "No riddle is running").await?;
+        return Ok(());
+    };
+
+    let Some(state_machine) = riddles
+        .lock()
+        .await
+        .get(&chat_state.riddle)
+        .and_then(|riddle| riddle.version(chat_state.version))
+        .map(|version| version.state_machine.clone())
+    else {
+        let code = chat_state.riddle;
+        return end_removed_riddle(&bot, &msg, key, sessions, &code, &riddles, &sessions_mut).await;
+    };
+
+    if let Err(error) = chat_state.undo(&state_machine) {
+        let text = match error {
+            UndoError::NotAllowed => "This riddle doesn't allow undoing answers",
+            UndoError::NoUndosLeft => "You have no undos left",
+            UndoError::Checkpoint => {
+                "You reached a checkpoint, the answers before it can't be undone"
+            }
+            UndoError::OutOfAttempts => {
+                "You ran out of attempts, the answers before that can't be undone"
+            }
+            UndoError::NothingToUndo => "There is nothing to undo",
+        };
+        reply(&bot, &msg, key, text).await?;
+        return Ok(());
+    }
+
+    let mut text = "You went back one step.".to_owned();
+    if let Some(limit) = state_machine.undo().and_then(|undo| undo.limit) {
+        text += &format!(" Undos left: {}.", limit - chat_state.undos);
+    }
+    if let Some(description) = state_machine.description(&chat_state.state) {
+        text += &format!("\n\n{}", description);
+    }
+    reply(&bot, &msg, key, text).await?;
+
+    chat_state.last_active = Utc::now();
+    sessions.update(chat_state);
+    riddles::update_sessions(sessions, key, &sessions_mut).await;
+    Ok(())
+}
+
+async fn command_transcript(
+    bot: Bot,
+    msg: Message,
+    sessions_mut: ChatData<Sessions>,
+) -> HandlerResult {
+    let key = riddles::sessions_key(&sessions_mut, &msg).await;
+    let sessions = riddles::get_data_at(&sessions_mut, key).await;
+    let Some(chat_state) = sessions.focused() else {
+        reply(&bot, &msg, key, "No riddle is running").await?;
+        return Ok(());
+    };
+
+    if chat_state.history.is_empty() {
+        reply(&bot, &msg, key, "You haven't answered yet").await?;
+    } else {
+        let mut text = chat_state.transcript().to_string();
+        if chat_state.team {
+            let names: Vec<&str> = chat_state
+                .history
+                .iter()
+                .map(|step| {
+                    step.user
+                        .and_then(|user| chat_state.members.get(&user))
+                        .map_or("?", |member| member.name.as_str())
+                })
+                .collect();
+            text.push_str(&format!("\n\nAnswered by: {}", names.join(", ")));
         }
-        DialogueState::StartRiddle => {
-            send_message(&bot, msg.chat.id, "No riddle is running").await?;
+        reply(&bot, &msg, key, text).await?;
+    }
+    Ok(())
+}
+
+// solvers shown on a leaderboard
+const LEADERBOARD_SIZE: usize = 10;
+
+async fn command_leaderboard(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    states: ChatData<DialogueState>,
+    rate_limits: RateLimits,
+    args: String,
+) -> HandlerResult {
+    if args.trim().is_empty() {
+        update_data(DialogueState::Leaderboard, msg.clone(), states).await;
+        send_message(
+            &bot,
+            msg.chat.id,
+            "What is the code of the riddle? Add `score` to rank the solvers by score instead of time.",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    update_data(DialogueState::None, msg.clone(), states).await;
+    show_leaderboard(bot, msg, riddles, rate_limits, &args).await
+}
+
+async fn command_leaderboard_code(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    states: ChatData<DialogueState>,
+    rate_limits: RateLimits,
+) -> HandlerResult {
+    let Some(args) = msg.text().map(str::to_owned) else {
+        return Ok(());
+    };
+    update_data(DialogueState::None, msg.clone(), states).await;
+    show_leaderboard(bot, msg, riddles, rate_limits, &args).await
+}
+
+async fn show_leaderboard(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    rate_limits: RateLimits,
+    args: &str,
+) -> HandlerResult {
+    let mut words = args.split_whitespace();
+    let code = words.next().unwrap_or_default();
+    let ranking = match words.next() {
+        None | Some("time") => Ranking::Time,
+        Some("score") => Ranking::Score,
+        Some(_) => {
+            send_message(&bot, msg.chat.id, "Rank by `time` or `score`").await?;
+            return Ok(());
         }
-        DialogueState::Riddle(_) => {
-            send_message(&bot, msg.chat.id, "Riddle stopped").await?;
-            update_data(DialogueState::None, msg, states).await;
+    };
+
+    if !throttling::allow(&bot, &msg, &rate_limits, throttling::Request::Code).await? {
+        return Ok(());
+    }
+
+    let user_id = msg.from().map(|user| user.id);
+    let riddles_lock = riddles.lock().await;
+    // drafts are only for their creator and admins, as in /startriddle
+    let privileged = |riddle: &riddles::Riddle| {
+        user_id.is_some_and(|id| id == riddle.creator || admin_commands::is_admin(id))
+    };
+    let Some(riddle) = riddles_lock
+        .get(code)
+        .filter(|riddle| riddle.published || privileged(riddle))
+    else {
+        send_message(&bot, msg.chat.id, "Riddle not found").await?;
+        return Ok(());
+    };
+
+    let solved = riddle
+        .solves
+        .iter()
+        .any(|solve| Some(solve.user) == user_id);
+    match riddle.current().state_machine.leaderboard() {
+        models_raw::LeaderboardVisibility::Hidden if !privileged(riddle) => {
+            send_message(
+                &bot,
+                msg.chat.id,
+                "The leaderboard of this riddle is hidden",
+            )
+            .await?;
+            return Ok(());
         }
+        models_raw::LeaderboardVisibility::Solvers if !privileged(riddle) && !solved => {
+            send_message(
+                &bot,
+                msg.chat.id,
+                "The leaderboard of this riddle is shown only to players who solved it",
+            )
+            .await?;
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let ranked = leaderboard::rank(&riddle.solves, ranking);
+    if ranked.is_empty() {
+        send_message(&bot, msg.chat.id, "Nobody has solved this riddle yet").await?;
+        return Ok(());
+    }
+
+    let line = |place: usize, solve: &leaderboard::Solve| {
+        format!(
+            "{}. {}{} - {}, score {}",
+            place + 1,
+            solve.name,
+            solve
+                .team
+                .as_ref()
+                .map(|team| format!(" (team {})", team))
+                .unwrap_or_default(),
+            format_duration(solve.time),
+            solve.score()
+        )
     };
+    let mut text = format!(
+        "{}, by {}:",
+        riddle.current().name,
+        match ranking {
+            Ranking::Time => "time",
+            Ranking::Score => "score",
+        }
+    );
+    for (place, solve) in ranked.iter().enumerate().take(LEADERBOARD_SIZE) {
+        text += &format!("\n{}", line(place, solve));
+    }
+    if let Some((place, solve)) = ranked
+        .iter()
+        .enumerate()
+        .skip(LEADERBOARD_SIZE)
+        .find(|(_, solve)| Some(solve.user) == user_id)
+    {
+        text += &format!("\n...\n{}", line(place, solve));
+    }
+
+    send_message(&bot, msg.chat.id, text).await?;
     Ok(())
 }
 
-async fn command_riddle(
+async fn command_hint(
     bot: Bot,
     msg: Message,
     riddles: riddles::Riddles,
-    chat_state: ChatState,
-    states: ChatData<DialogueState>,
+    sessions_mut: ChatData<Sessions>,
 ) -> HandlerResult {
-    let input = msg.text().unwrap();
+    let key = riddles::sessions_key(&sessions_mut, &msg).await;
+    let mut sessions = riddles::get_data_at(&sessions_mut, key).await;
+    let Some(mut chat_state) = sessions.focused().cloned() else {
+        reply(&bot, &msg, key, "No riddle is running").await?;
+        return Ok(());
+    };
 
     let riddles_lock = riddles.lock().await;
-    let riddle = riddles_lock.get(&chat_state.riddle).unwrap();
-
-    let new_state = riddle
-        .state_machine
-        .apply(
-            &mut Applier::new(&bot, msg.chat.id),
-            &chat_state.state,
-            input,
+    let Some(version) = riddles_lock
+        .get(&chat_state.riddle)
+        .and_then(|riddle| riddle.version(chat_state.version))
+    else {
+        drop(riddles_lock);
+        let code = chat_state.riddle;
+        return end_removed_riddle(&bot, &msg, key, sessions, &code, &riddles, &sessions_mut).await;
+    };
+
+    let hints = version.state_machine.hints(&chat_state.state);
+    let revealed = chat_state
+        .hints
+        .get(&chat_state.state)
+        .copied()
+        .unwrap_or(0);
+    let Some(hint) = hints.get(revealed) else {
+        let text = if hints.is_empty() {
+            "There are no hints for this step"
+        } else {
+            "You have seen all the hints for this step"
+        };
+        reply(&bot, &msg, key, text).await?;
+        return Ok(());
+    };
+
+    let now = Utc::now();
+    let available_at = chat_state.hint_clock + Duration::seconds(hint.cooldown.into());
+    if now < available_at {
+        reply(
+            &bot,
+            &msg,
+            key,
+            format!(
+                "The next hint is available in {} second(s)",
+                (available_at - now).num_seconds() + 1
+            ),
         )
         .await?;
+        return Ok(());
+    }
 
-    if riddle.state_machine.is_accepting(&new_state) {
-        send_message(&bot, msg.chat.id, "You solved the riddle!").await?;
-        update_data(DialogueState::None, msg, states).await;
-    } else {
-        update_data(
-            DialogueState::Riddle(ChatState {
-                riddle: chat_state.riddle,
-                state: new_state,
-            }),
-            msg,
-            states,
+    let mut text = format!("Hint {} of {}: {}", revealed + 1, hints.len(), hint.text);
+    if hint.penalty > 0 {
+        text += &format!("\n(-{} point(s))", hint.penalty);
+    }
+    reply(&bot, &msg, key, text).await?;
+
+    chat_state
+        .hints
+        .insert(chat_state.state.clone(), revealed + 1);
+    chat_state.hint_penalty += hint.penalty;
+    chat_state.hint_clock = now;
+    chat_state.last_active = now;
+    drop(riddles_lock);
+    sessions.update(chat_state);
+    riddles::update_sessions(sessions, key, &sessions_mut).await;
+
+    Ok(())
+}
+
+async fn command_status(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    sessions_mut: ChatData<Sessions>,
+) -> HandlerResult {
+    let key = riddles::sessions_key(&sessions_mut, &msg).await;
+    let sessions = riddles::get_data_at(&sessions_mut, key).await;
+    let Some(chat_state) = sessions.focused() else {
+        reply(&bot, &msg, key, "No riddle is running").await?;
+        return Ok(());
+    };
+
+    let riddles_lock = riddles.lock().await;
+    let Some(version) = riddles_lock
+        .get(&chat_state.riddle)
+        .and_then(|riddle| riddle.version(chat_state.version))
+    else {
+        drop(riddles_lock);
+        let code = chat_state.riddle.clone();
+        return end_removed_riddle(&bot, &msg, key, sessions, &code, &riddles, &sessions_mut).await;
+    };
+
+    let mut status = format!(
+        "Riddle: {}{}\nTime spent: {}\nAnswers: {} ({} wrong)\nHints used: {}",
+        version.name,
+        if chat_state.testing {
+            " (playtest)"
+        } else if chat_state.team {
+            " (team)"
+        } else {
+            ""
+        },
+        format_duration(chat_state.time_spent(Utc::now())),
+        chat_state.answers,
+        chat_state.wrong_answers,
+        chat_state.hints_used()
+    );
+    if chat_state.hint_penalty > 0 {
+        status += &format!(" (-{} point(s))", chat_state.hint_penalty);
+    }
+    if chat_state.team && !chat_state.members.is_empty() {
+        status += &format!("\n{}", members_summary(chat_state));
+    }
+    if let Some(description) = version.state_machine.description(&chat_state.state) {
+        status += &format!("\n\n{}", description);
+    }
+
+    reply(&bot, &msg, key, status).await?;
+    Ok(())
+}
+
+async fn command_riddle(
+    bot: Bot,
+    msg: Message,
+    riddles: riddles::Riddles,
+    races: riddles::Races,
+    sessions_mut: ChatData<Sessions>,
+    rate_limits: RateLimits,
+) -> HandlerResult {
+    let Some(input) = msg.text() else {
+        return Ok(());
+    };
+
+    let key = riddles::sessions_key(&sessions_mut, &msg).await;
+    let mut sessions = riddles::get_data_at(&sessions_mut, key).await;
+    let Some(mut chat_state) = sessions.focused().cloned() else {
+        return Ok(());
+    };
+
+    let now = Utc::now();
+    if let Some(locked_until) = chat_state.locked_until.filter(|until| now < *until) {
+        reply(
+            &bot,
+            &msg,
+            key,
+            format!(
+                "You ran out of attempts, you can answer again in {} second(s)",
+                (locked_until - now).num_seconds() + 1
+            ),
         )
-        .await;
+        .await?;
+        return Ok(());
+    }
+
+    let Some(state_machine) = riddles
+        .lock()
+        .await
+        .get(&chat_state.riddle)
+        .and_then(|riddle| riddle.version(chat_state.version))
+        .map(|version| version.state_machine.clone())
+    else {
+        let code = chat_state.riddle;
+        return end_removed_riddle(&bot, &msg, key, sessions, &code, &riddles, &sessions_mut).await;
+    };
+
+    let request = throttling::Request::Answer {
+        riddle: chat_state.riddle.clone(),
+        limit: state_machine.rate_limit(),
+    };
+    if !throttling::allow(&bot, &msg, &rate_limits, request).await? {
+        return Ok(());
+    }
+
+    let to = if chat_state.testing {
+        let trace = state_machine
+            .trace(
+                &mut SandboxApplier {
+                    bot: &bot,
+                    chat_id: msg.chat.id,
+                },
+                &chat_state.state,
+                input,
+            )
+            .await?;
+        reply(&bot, &msg, key, trace.to_string()).await?;
+        (!trace.wrong).then_some(trace.to)
+    } else {
+        state_machine
+            .apply_edge(
+                &mut Applier::new(&bot, msg.chat.id, addressee(&msg, key)),
+                &chat_state.state,
+                input,
+            )
+            .await?
+    };
+
+    chat_state.last_active = now;
+    let from = chat_state.state.clone();
+    let answer = state_machine.count_attempt(&from, to, &mut chat_state.attempts);
+    chat_state.history.push(riddles::Move {
+        input: input.to_owned(),
+        from: from.clone(),
+        to: answer.state.clone(),
+        user: msg.from().map(|user| user.id),
+        out_of_attempts: answer.out_of_attempts,
+    });
+    chat_state.answers += 1;
+    if answer.wrong {
+        chat_state.wrong_answers += 1;
+    }
+    if let Some(user) = msg.from() {
+        let member = chat_state
+            .members
+            .entry(user.id)
+            .or_insert_with(|| riddles::Member {
+                name: user.full_name(),
+                answers: 0,
+                wrong_answers: 0,
+            });
+        member.answers += 1;
+        if answer.wrong {
+            member.wrong_answers += 1;
+        }
+    }
+    if answer.out_of_attempts {
+        let mut text = "You ran out of attempts!".to_owned();
+        if answer.lockout > 0 {
+            chat_state.locked_until = Some(now + Duration::seconds(answer.lockout.into()));
+            text += &format!(" You can answer again in {} second(s).", answer.lockout);
+        }
+        reply(&bot, &msg, key, text).await?;
+    }
+
+    // races are played by the team sessions they started
+    let race_transition =
+        (chat_state.team && !chat_state.testing && key.user.is_none()).then(|| race::Transition {
+            from: from.clone(),
+            to: answer.state.clone(),
+            at: now,
+        });
+    let code = chat_state.riddle.clone();
+
+    if answer.wrong && !chat_state.testing {
+        if let Some(riddle) = riddles.lock().await.get_mut(&chat_state.riddle) {
+            *riddle.stats.wrong_answers.entry(from.clone()).or_default() += 1;
+            if answer.out_of_attempts {
+                *riddle.stats.out_of_attempts.entry(from).or_default() += 1;
+            }
+        }
+    }
+
+    let new_state = answer.state;
+    if state_machine.is_accepting(&new_state) {
+        let team = chat_state
+            .team
+            .then(|| msg.chat.title().unwrap_or("a group").to_owned());
+        // every member who answered is credited with the solve, solves
+        // without a sender (e.g. in channels) are not recorded. Leaderboards
+        // rank by wall-clock time, pauses included, so that pausing to work
+        // out answers offline doesn't pay off.
+        let time = now - chat_state.started_at;
+        let solves: Vec<leaderboard::Solve> = chat_state
+            .members
+            .iter()
+            .filter(|(id, _)| chat_state.team || msg.from().is_some_and(|user| user.id == **id))
+            .map(|(id, member)| leaderboard::Solve {
+                user: *id,
+                name: member.name.clone(),
+                team: team.clone(),
+                started_at: chat_state.started_at,
+                finished_at: now,
+                time,
+                answers: chat_state.answers,
+                wrong_answers: chat_state.wrong_answers,
+                hints: chat_state.hints_used(),
+                hint_penalty: chat_state.hint_penalty,
+            })
+            .collect();
+
+        let mut text = format!(
+            "{} the riddle in {}! Score: {}",
+            if chat_state.team {
+                "Your team solved"
+            } else {
+                "You solved"
+            },
+            format_duration(time),
+            leaderboard::score(chat_state.wrong_answers, chat_state.hint_penalty)
+        );
+        if chat_state.team {
+            text += &format!("\n{}", members_summary(&chat_state));
+        }
+        if state_machine.leaderboard() != models_raw::LeaderboardVisibility::Hidden {
+            text += &format!("\nSee /leaderboard {}", chat_state.riddle);
+        }
+        if !chat_state.testing {
+            if let Some(riddle) = riddles.lock().await.get_mut(&chat_state.riddle) {
+                riddle.solves.extend(solves);
+            }
+        }
+
+        if chat_state.hints_used() > 0 {
+            text += &format!(
+                "\nHints used: {}, penalty: {} point(s)",
+                chat_state.hints_used(),
+                chat_state.hint_penalty
+            );
+        }
+        if let Some(next) = sessions.end(&chat_state.riddle) {
+            text += &format!(
+                "\nYour answers go to {} now.",
+                session_title(&*riddles.lock().await, next)
+            );
+        }
+        reply(&bot, &msg, key, text).await?;
+    } else {
+        chat_state.move_to(new_state);
+        sessions.update(chat_state);
+    }
+    riddles::update_sessions(sessions, key, &sessions_mut).await;
+
+    if let Some(transition) = race_transition {
+        record_race(&bot, &races, msg.chat.id, &code, &state_machine, transition).await?;
     }
 
     Ok(())
 }
+
+/// Records the transition of the team playing in `chat`, if it is in a race
+/// on the riddle, and announces the team's milestones and finish. Once every
+/// team finished, the final standings are posted and the race is over.
+async fn record_race(
+    bot: &Bot,
+    races: &riddles::Races,
+    chat: ChatId,
+    code: &str,
+    state_machine: &StateMachine,
+    transition: race::Transition,
+) -> HandlerResult {
+    let mut races_lock = races.lock().await;
+    let Some(race) = races_lock.get_mut(code) else {
+        return Ok(());
+    };
+
+    let at = transition.at;
+    let milestone = state_machine.is_milestone(&transition.to);
+    let accepting = state_machine.is_accepting(&transition.to);
+    let Some(progress) = race.record(chat, transition, milestone, accepting) else {
+        return Ok(());
+    };
+
+    let team = &race.team(chat).unwrap().name;
+    let elapsed = format_duration(race.elapsed(at));
+    let mut announcements = vec![match progress {
+        Progress::Milestone(reached) => format!(
+            "Race on {}: {} reached milestone {} of {} after {}",
+            race.name,
+            team,
+            reached,
+            state_machine.milestone_count(),
+            elapsed
+        ),
+        Progress::Finished(place) => format!(
+            "Race on {}: {} finished in place {} after {}!",
+            race.name, team, place, elapsed
+        ),
+    }];
+    let team_text = match progress {
+        Progress::Finished(place) => Some(format!(
+            "You finished the race in place {} of {}!",
+            place,
+            race.teams.len()
+        )),
+        Progress::Milestone(_) => None,
+    };
+    let announce_to = race.announcements;
+    if race.is_over() {
+        announcements.push(race_standings(race));
+        races_lock.remove(code);
+    }
+    drop(races_lock);
+
+    if let Some(text) = team_text {
+        send_message(bot, chat, text).await?;
+    }
+    for text in announcements {
+        // the announcement chat may have removed the bot
+        if let Err(e) = send_message(bot, announce_to, text).await {
+            log::warn!(
+                "Cannot announce the race on {} in chat {}: {}",
+                code,
+                announce_to,
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The final standings of a race, followed by the transitions of every team
+/// and when they happened.
+pub(crate) fn race_standings(race: &Race) -> String {
+    let mut text = format!("Final standings of the race on {}:", race.name);
+    for (place, team) in race.standings().iter().enumerate() {
+        text += &format!("\n{}. {} - ", place + 1, team.name);
+        text += &match team.finished_at {
+            Some(finished_at) => format_duration(race.elapsed(finished_at)),
+            None => format!(
+                "did not finish, {} milestone(s) reached",
+                team.milestones.len()
+            ),
+        };
+    }
+    for team in race.standings() {
+        text += &format!("\n\n{}:", team.name);
+        if team.transitions.is_empty() {
+            text += " no progress";
+        }
+        for transition in &team.transitions {
+            text += &format!(
+                "\n{} {} -> {}",
+                format_duration(race.elapsed(transition.at)),
+                transition.from,
+                transition.to
+            );
+        }
+    }
+    text
+}
@@ -0,0 +1,995 @@
+//! A compact, line-oriented text format for state machines.
+//!
+//! ```text
+//! # comments start with a hash
+//! initial start
+//! accept end
+//! start --"hello"--> middle : say "Hi"
+//! middle --"^h.*o$"/regex--> end : say "Well done", send 12345 "Someone solved it"
+//! middle --*--> . : say "Nope"
+//! ```
+//!
+//! Every state mentioned anywhere in the file is declared, in order of first
+//! appearance. `state NAME` declares a state without any edges, `state NAME
+//! "description"` also describes it to players.
+//!
+//! Hints are added to a state in the order they are written, optionally with
+//! a cooldown in seconds and a penalty in points:
+//!
+//! ```text
+//! hint middle "It's a greeting" cooldown 60 penalty 5
+//! ```
+//!
+//! The number of wrong answers in a state can be limited. Players who run
+//! out of attempts are sent to a failure state, locked out for a number of
+//! seconds, or both:
+//!
+//! ```text
+//! attempts middle 3 fail start lockout 300
+//! ```
+//!
+//! `rate_limit 5 per 60` allows players at most 5 answers every 60 seconds.
+//!
+//! `undo` lets players take back their answers with `/undo`, `undo 3` at most
+//! three times. Answers that led to a checkpoint can't be undone:
+//!
+//! ```text
+//! checkpoint middle, end
+//! ```
+//!
+//! In races, reaching a milestone is announced:
+//!
+//! ```text
+//! milestone middle
+//! ```
+//!
+//! `leaderboard solvers` shows the leaderboard only to players who solved the
+//! riddle, `leaderboard hidden` hides it, `leaderboard public` is the default.
+//!
+//! `play_mode solo` or `play_mode team` allows playing the riddle only alone
+//! or only as a group, `play_mode both` is the default.
+//!
+//! Example playthroughs are written as blocks:
+//!
+//! ```text
+//! test "shortest solution"
+//! > "hello"
+//! < "Hi"
+//! expect accept end
+//! ```
+
+use std::fmt;
+
+use crate::models_raw;
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+type ParseResult<T> = Result<T, ParseError>;
+
+struct Cursor {
+    line: usize,
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Cursor {
+    fn new(line: usize, source: &str) -> Self {
+        Self {
+            line,
+            chars: source.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> ParseResult<T> {
+        Err(ParseError {
+            line: self.line,
+            column: self.pos + 1,
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_whitespace();
+        matches!(self.peek(), None | Some('#'))
+    }
+
+    fn eat(&mut self, expected: &str) -> bool {
+        self.skip_whitespace();
+        let len = expected.chars().count();
+        let matches = self.chars.len() >= self.pos + len
+            && self.chars[self.pos..self.pos + len]
+                .iter()
+                .copied()
+                .eq(expected.chars());
+        if matches {
+            self.pos += len;
+        }
+        matches
+    }
+
+    fn expect(&mut self, expected: &str) -> ParseResult<()> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            self.error(format!("expected `{}`", expected))
+        }
+    }
+
+    fn expect_end(&mut self) -> ParseResult<()> {
+        if self.at_end() {
+            Ok(())
+        } else {
+            self.error("unexpected trailing input")
+        }
+    }
+
+    fn word(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            None
+        } else {
+            Some(self.chars[start..self.pos].iter().collect())
+        }
+    }
+
+    fn string(&mut self) -> ParseResult<String> {
+        self.skip_whitespace();
+        if self.peek() != Some('"') {
+            return self.error("expected a string literal");
+        }
+        self.pos += 1;
+
+        let mut result = String::new();
+        loop {
+            match self.peek() {
+                None => return self.error("unterminated string literal"),
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(result);
+                }
+                Some('\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        _ => return self.error("unknown escape sequence"),
+                    };
+                    result.push(escaped);
+                    self.pos += 1;
+                }
+                Some(c) => {
+                    result.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn state_name(&mut self) -> ParseResult<String> {
+        self.skip_whitespace();
+        if self.peek() == Some('"') {
+            return self.string();
+        }
+        match self.word() {
+            Some(name) => Ok(name),
+            None => self.error("expected a state name"),
+        }
+    }
+
+    fn integer(&mut self) -> ParseResult<i64> {
+        self.skip_whitespace();
+        let start = self.pos;
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        }
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        match text.parse() {
+            Ok(value) => Ok(value),
+            Err(_) => {
+                self.pos = start;
+                self.error("expected an integer")
+            }
+        }
+    }
+
+    fn count(&mut self) -> ParseResult<u32> {
+        self.skip_whitespace();
+        let start = self.pos;
+        match u32::try_from(self.integer()?) {
+            Ok(count) => Ok(count),
+            Err(_) => {
+                self.pos = start;
+                self.error("expected a non-negative integer")
+            }
+        }
+    }
+}
+
+struct Builder {
+    initial_state: Option<String>,
+    accepting_states: Vec<String>,
+    states: Vec<models_raw::State>,
+    tests: Vec<models_raw::Playthrough>,
+    rate_limit: Option<models_raw::RateLimit>,
+    undo: Option<models_raw::Undo>,
+    leaderboard: Option<models_raw::LeaderboardVisibility>,
+    play_mode: Option<models_raw::PlayMode>,
+    // a test that is missing its `expect` line, with the line it started on
+    open_test: Option<(usize, String, Vec<models_raw::Step>)>,
+}
+
+impl Builder {
+    fn state(&mut self, name: &str) -> &mut models_raw::State {
+        let index = match self.states.iter().position(|state| state.name == name) {
+            Some(index) => index,
+            None => {
+                self.states.push(models_raw::State {
+                    name: name.to_owned(),
+                    ..Default::default()
+                });
+                self.states.len() - 1
+            }
+        };
+        &mut self.states[index]
+    }
+}
+
+fn parse_prompt(cursor: &mut Cursor) -> ParseResult<models_raw::Prompt> {
+    if cursor.eat("*") {
+        return Ok(models_raw::Prompt::Either);
+    }
+
+    let content = cursor.string()?;
+    if !cursor.eat("/") {
+        return Ok(models_raw::Prompt::Text(content));
+    }
+
+    let kind_column = cursor.pos;
+    match cursor.word().as_deref() {
+        Some("text") => Ok(models_raw::Prompt::Text(content)),
+        Some("regex") => {
+            if let Err(e) = regex::Regex::new(&content) {
+                cursor.pos = kind_column;
+                return cursor.error(format!("invalid regex: {}", e));
+            }
+            Ok(models_raw::Prompt::Regex(content))
+        }
+        _ => {
+            cursor.pos = kind_column;
+            cursor.error("expected prompt kind `text` or `regex`")
+        }
+    }
+}
+
+fn parse_action(cursor: &mut Cursor) -> ParseResult<models_raw::Action> {
+    let start = cursor.pos;
+    match cursor.word().as_deref() {
+        Some("say") => Ok(models_raw::Action::Message(cursor.string()?)),
+        Some("send") => {
+            let chat_id = cursor.integer()?;
+            let message = cursor.string()?;
+            Ok(models_raw::Action::SendTo { chat_id, message })
+        }
+        _ => {
+            cursor.pos = start;
+            cursor.skip_whitespace();
+            cursor.error("expected an action (`say` or `send`)")
+        }
+    }
+}
+
+fn parse_edge(cursor: &mut Cursor, builder: &mut Builder) -> ParseResult<()> {
+    let from = cursor.state_name()?;
+    cursor.expect("--")?;
+    let prompt = parse_prompt(cursor)?;
+    cursor.expect("-->")?;
+
+    let next = if cursor.eat(".") {
+        None
+    } else {
+        Some(cursor.state_name()?)
+    };
+
+    let mut actions = vec![];
+    if cursor.eat(":") {
+        loop {
+            actions.push(parse_action(cursor)?);
+            if !cursor.eat(",") {
+                break;
+            }
+        }
+    }
+    cursor.expect_end()?;
+
+    builder.state(&from);
+    if let Some(next) = next.as_ref() {
+        builder.state(next);
+    }
+    builder.state(&from).edges.push(models_raw::Edge {
+        prompt,
+        actions,
+        next,
+    });
+    Ok(())
+}
+
+fn parse_test_line(cursor: &mut Cursor, builder: &mut Builder) -> ParseResult<bool> {
+    let start = cursor.pos;
+    if cursor.eat(">") || cursor.eat("<") {
+        let is_input = cursor.chars[cursor.pos - 1] == '>';
+        // `< (none)` expects the input to send no messages at all
+        let text = if !is_input && cursor.eat("(none)") {
+            None
+        } else {
+            Some(cursor.string()?)
+        };
+        cursor.expect_end()?;
+
+        let Some((_, _, steps)) = builder.open_test.as_mut() else {
+            cursor.pos = start;
+            return cursor.error("steps must follow a `test` line");
+        };
+        if is_input {
+            steps.push(models_raw::Step {
+                input: text.unwrap(),
+                messages: None,
+            });
+        } else {
+            let Some(step) = steps.last_mut() else {
+                cursor.pos = start;
+                return cursor.error("expected messages must follow an input");
+            };
+            match (text, &mut step.messages) {
+                (None, None) => step.messages = Some(vec![]),
+                (Some(text), None) => step.messages = Some(vec![text]),
+                (Some(text), Some(messages)) if !messages.is_empty() => messages.push(text),
+                _ => {
+                    cursor.pos = start;
+                    return cursor.error("`< (none)` can't be combined with other messages");
+                }
+            }
+        }
+        return Ok(true);
+    }
+
+    match cursor.word().as_deref() {
+        Some("test") if !cursor.eat("--") => {
+            if builder.open_test.is_some() {
+                cursor.pos = start;
+                return cursor.error("the previous test is missing its `expect` line");
+            }
+            let name = cursor.string()?;
+            cursor.expect_end()?;
+            builder.open_test = Some((cursor.line, name, vec![]));
+            Ok(true)
+        }
+        Some("expect") if !cursor.eat("--") => {
+            let accepts_column = cursor.pos;
+            let accepts = match cursor.word().as_deref() {
+                Some("accept") => true,
+                Some("reject") => false,
+                _ => {
+                    cursor.pos = accepts_column;
+                    cursor.skip_whitespace();
+                    return cursor.error("expected `accept` or `reject`");
+                }
+            };
+            let final_state = cursor.state_name()?;
+            cursor.expect_end()?;
+
+            let Some((_, name, steps)) = builder.open_test.take() else {
+                cursor.pos = start;
+                return cursor.error("`expect` must follow a `test` line");
+            };
+            builder.tests.push(models_raw::Playthrough {
+                name,
+                steps,
+                final_state,
+                accepts,
+            });
+            Ok(true)
+        }
+        _ => {
+            cursor.pos = start;
+            Ok(false)
+        }
+    }
+}
+
+fn parse_line(cursor: &mut Cursor, builder: &mut Builder) -> ParseResult<()> {
+    if parse_test_line(cursor, builder)? {
+        return Ok(());
+    }
+
+    let start = cursor.pos;
+    match cursor.word().as_deref() {
+        Some("initial") if !cursor.eat("--") => {
+            let name = cursor.state_name()?;
+            if builder.initial_state.is_some() {
+                cursor.pos = start;
+                return cursor.error("initial state is already defined");
+            }
+            cursor.expect_end()?;
+            builder.state(&name);
+            builder.initial_state = Some(name);
+            Ok(())
+        }
+        Some("accept") if !cursor.eat("--") => {
+            loop {
+                let name = cursor.state_name()?;
+                builder.state(&name);
+                if !builder.accepting_states.contains(&name) {
+                    builder.accepting_states.push(name);
+                }
+                if !cursor.eat(",") {
+                    break;
+                }
+            }
+            cursor.expect_end()
+        }
+        Some("state") if !cursor.eat("--") => {
+            let name = cursor.state_name()?;
+            let description = if cursor.at_end() {
+                None
+            } else {
+                Some(cursor.string()?)
+            };
+            cursor.expect_end()?;
+
+            let state = builder.state(&name);
+            if description.is_some() {
+                if state.description.is_some() {
+                    cursor.pos = start;
+                    return cursor.error(format!("state {} is already described", name));
+                }
+                state.description = description;
+            }
+            Ok(())
+        }
+        Some("hint") if !cursor.eat("--") => {
+            let name = cursor.state_name()?;
+            let mut hint = models_raw::Hint {
+                text: cursor.string()?,
+                cooldown: 0,
+                penalty: 0,
+            };
+            while !cursor.at_end() {
+                let option_column = cursor.pos;
+                let value = match cursor.word().as_deref() {
+                    Some("cooldown") => &mut hint.cooldown,
+                    Some("penalty") => &mut hint.penalty,
+                    _ => {
+                        cursor.pos = option_column;
+                        return cursor.error("expected `cooldown` or `penalty`");
+                    }
+                };
+                *value = cursor.count()?;
+            }
+            builder.state(&name).hints.push(hint);
+            Ok(())
+        }
+        Some("rate_limit") if !cursor.eat("--") => {
+            if builder.rate_limit.is_some() {
+                cursor.pos = start;
+                return cursor.error("rate limit is already defined");
+            }
+            let requests = cursor.count()?;
+            let per_column = cursor.pos;
+            if cursor.word().as_deref() != Some("per") {
+                cursor.pos = per_column;
+                cursor.skip_whitespace();
+                return cursor.error("expected `per`");
+            }
+            let seconds = cursor.count()?;
+            cursor.expect_end()?;
+            builder.rate_limit = Some(models_raw::RateLimit { requests, seconds });
+            Ok(())
+        }
+        Some("undo") if !cursor.eat("--") => {
+            if builder.undo.is_some() {
+                cursor.pos = start;
+                return cursor.error("undo is already allowed");
+            }
+            let limit = if cursor.at_end() {
+                None
+            } else {
+                Some(cursor.count()?)
+            };
+            cursor.expect_end()?;
+            builder.undo = Some(models_raw::Undo { limit });
+            Ok(())
+        }
+        Some("leaderboard") if !cursor.eat("--") => {
+            if builder.leaderboard.is_some() {
+                cursor.pos = start;
+                return cursor.error("leaderboard is already defined");
+            }
+            let value_column = cursor.pos;
+            let visibility = match cursor.word().as_deref() {
+                Some("public") => models_raw::LeaderboardVisibility::Public,
+                Some("solvers") => models_raw::LeaderboardVisibility::Solvers,
+                Some("hidden") => models_raw::LeaderboardVisibility::Hidden,
+                _ => {
+                    cursor.pos = value_column;
+                    cursor.skip_whitespace();
+                    return cursor.error("expected `public`, `solvers` or `hidden`");
+                }
+            };
+            cursor.expect_end()?;
+            builder.leaderboard = Some(visibility);
+            Ok(())
+        }
+        Some("play_mode") if !cursor.eat("--") => {
+            if builder.play_mode.is_some() {
+                cursor.pos = start;
+                return cursor.error("play mode is already defined");
+            }
+            let value_column = cursor.pos;
+            let play_mode = match cursor.word().as_deref() {
+                Some("solo") => models_raw::PlayMode::Solo,
+                Some("team") => models_raw::PlayMode::Team,
+                Some("both") => models_raw::PlayMode::Both,
+                _ => {
+                    cursor.pos = value_column;
+                    cursor.skip_whitespace();
+                    return cursor.error("expected `solo`, `team` or `both`");
+                }
+            };
+            cursor.expect_end()?;
+            builder.play_mode = Some(play_mode);
+            Ok(())
+        }
+        Some("checkpoint") if !cursor.eat("--") => {
+            loop {
+                let name = cursor.state_name()?;
+                builder.state(&name).checkpoint = true;
+                if !cursor.eat(",") {
+                    break;
+                }
+            }
+            cursor.expect_end()
+        }
+        Some("milestone") if !cursor.eat("--") => {
+            loop {
+                let name = cursor.state_name()?;
+                builder.state(&name).milestone = true;
+                if !cursor.eat(",") {
+                    break;
+                }
+            }
+            cursor.expect_end()
+        }
+        Some("attempts") if !cursor.eat("--") => {
+            let name = cursor.state_name()?;
+            let max_attempts = cursor.count()?;
+            let mut failure_state = None;
+            let mut lockout = 0;
+            while !cursor.at_end() {
+                let option_column = cursor.pos;
+                match cursor.word().as_deref() {
+                    Some("fail") => {
+                        let failure = cursor.state_name()?;
+                        builder.state(&failure);
+                        failure_state = Some(failure);
+                    }
+                    Some("lockout") => lockout = cursor.count()?,
+                    _ => {
+                        cursor.pos = option_column;
+                        return cursor.error("expected `fail` or `lockout`");
+                    }
+                }
+            }
+
+            let state = builder.state(&name);
+            if state.max_attempts.is_some() {
+                cursor.pos = start;
+                return cursor.error(format!("attempts of {} are already limited", name));
+            }
+            state.max_attempts = Some(max_attempts);
+            state.failure_state = failure_state;
+            state.lockout = lockout;
+            Ok(())
+        }
+        _ => {
+            cursor.pos = start;
+            parse_edge(cursor, builder)
+        }
+    }
+}
+
+pub fn parse(source: &str) -> ParseResult<models_raw::StateMachine> {
+    let mut builder = Builder {
+        initial_state: None,
+        accepting_states: vec![],
+        states: vec![],
+        tests: vec![],
+        rate_limit: None,
+        undo: None,
+        leaderboard: None,
+        play_mode: None,
+        open_test: None,
+    };
+
+    let mut line_count = 0;
+    for (index, line) in source.lines().enumerate() {
+        line_count = index + 1;
+        let mut cursor = Cursor::new(index + 1, line);
+        if cursor.at_end() {
+            continue;
+        }
+        parse_line(&mut cursor, &mut builder)?;
+    }
+
+    if let Some((line, _, _)) = builder.open_test {
+        return Err(ParseError {
+            line,
+            column: 1,
+            message: "the test is missing its `expect` line".to_owned(),
+        });
+    }
+
+    let initial_state = builder.initial_state.ok_or(ParseError {
+        line: line_count.max(1),
+        column: 1,
+        message: "missing `initial` declaration".to_owned(),
+    })?;
+
+    Ok(models_raw::StateMachine {
+        initial_state,
+        accepting_states: builder.accepting_states,
+        states: builder.states,
+        tests: builder.tests,
+        rate_limit: builder.rate_limit,
+        undo: builder.undo,
+        leaderboard: builder.leaderboard.unwrap_or_default(),
+        play_mode: builder.play_mode.unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models_raw::{Action, Edge, Prompt, State, StateMachine};
+
+    #[test]
+    fn simple_dsl() {
+        let source = r#"
+            # a tiny riddle
+            initial start
+            accept end
+            start --"hello"/regex--> end : say "Hi"
+            start --"hi"--> . : say "Say \"hello\"", send -42 "someone is trying"
+            start --*--> start
+        "#;
+
+        assert_eq!(
+            parse(source).unwrap(),
+            StateMachine {
+                initial_state: "start".to_string(),
+                accepting_states: vec!["end".to_string()],
+                states: vec![
+                    State {
+                        name: "start".to_string(),
+                        edges: vec![
+                            Edge {
+                                prompt: Prompt::Regex("hello".to_string()),
+                                actions: vec![Action::Message("Hi".to_string())],
+                                next: Some("end".to_string()),
+                            },
+                            Edge {
+                                prompt: Prompt::Text("hi".to_string()),
+                                actions: vec![
+                                    Action::Message("Say \"hello\"".to_string()),
+                                    Action::SendTo {
+                                        chat_id: -42,
+                                        message: "someone is trying".to_string(),
+                                    },
+                                ],
+                                next: None,
+                            },
+                            Edge {
+                                prompt: Prompt::Either,
+                                actions: vec![],
+                                next: Some("start".to_string()),
+                            },
+                        ],
+                        ..Default::default()
+                    },
+                    State {
+                        name: "end".to_string(),
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn tests() {
+        let source = r#"
+            initial a
+            a --"x"--> b : say "1", say "2"
+            test "two steps"
+            > "y"
+            > "x"
+            < "1"
+            < "2"
+            expect reject b
+            test "silent"
+            > "y"
+            < (none)
+            expect reject a
+        "#;
+
+        assert_eq!(
+            parse(source).unwrap().tests,
+            vec![
+                models_raw::Playthrough {
+                    name: "two steps".to_string(),
+                    steps: vec![
+                        models_raw::Step {
+                            input: "y".to_string(),
+                            messages: None,
+                        },
+                        models_raw::Step {
+                            input: "x".to_string(),
+                            messages: Some(vec!["1".to_string(), "2".to_string()]),
+                        },
+                    ],
+                    final_state: "b".to_string(),
+                    accepts: false,
+                },
+                models_raw::Playthrough {
+                    name: "silent".to_string(),
+                    steps: vec![models_raw::Step {
+                        input: "y".to_string(),
+                        messages: Some(vec![]),
+                    }],
+                    final_state: "a".to_string(),
+                    accepts: false,
+                }
+            ]
+        );
+        // test blocks are written back the way they are parsed
+        let tests = parse(source).unwrap().tests;
+        assert_eq!(
+            parse(&format!("initial a\n{}", tests[1])).unwrap().tests,
+            tests[1..]
+        );
+        assert_eq!(
+            parse("initial a\ntest \"t\"\n> \"x\"\n< \"1\"\n< (none)\nexpect reject a")
+                .unwrap_err(),
+            ParseError {
+                line: 5,
+                column: 1,
+                message: "`< (none)` can't be combined with other messages".to_string(),
+            }
+        );
+        assert_eq!(
+            parse("initial a\ntest \"t\"\n> \"x\"").unwrap_err(),
+            ParseError {
+                line: 2,
+                column: 1,
+                message: "the test is missing its `expect` line".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn hints() {
+        let state_machine = parse(
+            r#"
+                initial a
+                hint a "first"
+                hint a "second" penalty 5 cooldown 60
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            state_machine.states[0].hints,
+            vec![
+                models_raw::Hint {
+                    text: "first".to_string(),
+                    cooldown: 0,
+                    penalty: 0,
+                },
+                models_raw::Hint {
+                    text: "second".to_string(),
+                    cooldown: 60,
+                    penalty: 5,
+                },
+            ]
+        );
+        assert_eq!(
+            parse("initial a\nhint a \"x\" penalty -1")
+                .unwrap_err()
+                .column,
+            20
+        );
+    }
+
+    #[test]
+    fn descriptions() {
+        let state_machine =
+            parse("initial a\nstate a \"You are in the library\"\nstate b").unwrap();
+
+        assert_eq!(
+            state_machine.states[0].description.as_deref(),
+            Some("You are in the library")
+        );
+        assert_eq!(state_machine.states[1].description, None);
+        assert!(parse("initial a\nstate a \"x\"\nstate a \"y\"").is_err());
+    }
+
+    #[test]
+    fn attempts() {
+        let state_machine = parse("initial a\nattempts a 3 lockout 60 fail b").unwrap();
+
+        assert_eq!(
+            state_machine.states,
+            vec![
+                State {
+                    name: "a".to_string(),
+                    max_attempts: Some(3),
+                    failure_state: Some("b".to_string()),
+                    lockout: 60,
+                    ..Default::default()
+                },
+                State {
+                    name: "b".to_string(),
+                    ..Default::default()
+                },
+            ]
+        );
+        assert_eq!(
+            parse("initial a\nrate_limit 5 per 60").unwrap().rate_limit,
+            Some(models_raw::RateLimit {
+                requests: 5,
+                seconds: 60,
+            })
+        );
+        assert_eq!(
+            parse("initial a\nattempts a 3\nattempts a 4")
+                .unwrap_err()
+                .line,
+            3
+        );
+    }
+
+    #[test]
+    fn undo() {
+        let state_machine = parse("initial a\nundo 3\ncheckpoint b, c").unwrap();
+
+        assert_eq!(
+            state_machine.undo,
+            Some(models_raw::Undo { limit: Some(3) })
+        );
+        assert_eq!(
+            state_machine
+                .states
+                .iter()
+                .map(|state| (state.name.as_str(), state.checkpoint))
+                .collect::<Vec<_>>(),
+            vec![("a", false), ("b", true), ("c", true)]
+        );
+        assert_eq!(
+            parse("initial a\nundo").unwrap().undo,
+            Some(models_raw::Undo { limit: None })
+        );
+        assert_eq!(parse("initial a").unwrap().undo, None);
+        assert_eq!(parse("initial a\nundo\nundo 2").unwrap_err().line, 3);
+    }
+
+    #[test]
+    fn milestone() {
+        let state_machine = parse("initial a\nmilestone b\nmilestone c, a").unwrap();
+
+        assert_eq!(
+            state_machine
+                .states
+                .iter()
+                .map(|state| (state.name.as_str(), state.milestone))
+                .collect::<Vec<_>>(),
+            vec![("a", true), ("b", true), ("c", true)]
+        );
+        assert_eq!(parse("initial a\nmilestone").unwrap_err().line, 2);
+    }
+
+    #[test]
+    fn leaderboard() {
+        assert_eq!(
+            parse("initial a\nleaderboard solvers").unwrap().leaderboard,
+            models_raw::LeaderboardVisibility::Solvers
+        );
+        assert_eq!(
+            parse("initial a").unwrap().leaderboard,
+            models_raw::LeaderboardVisibility::Public
+        );
+        assert_eq!(
+            parse("initial a\nleaderboard everyone").unwrap_err(),
+            ParseError {
+                line: 2,
+                column: 13,
+                message: "expected `public`, `solvers` or `hidden`".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn play_mode() {
+        assert_eq!(
+            parse("initial a\nplay_mode team").unwrap().play_mode,
+            models_raw::PlayMode::Team
+        );
+        assert_eq!(
+            parse("initial a").unwrap().play_mode,
+            models_raw::PlayMode::Both
+        );
+        assert_eq!(
+            parse("initial a\nplay_mode solo\nplay_mode team")
+                .unwrap_err()
+                .line,
+            3
+        );
+        assert!(parse("initial a\nplay_mode duo").is_err());
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        assert_eq!(
+            parse("initial a\na --\"x\"-> b").unwrap_err(),
+            ParseError {
+                line: 2,
+                column: 8,
+                message: "expected `-->`".to_string(),
+            }
+        );
+        assert_eq!(
+            parse("initial a\na --\"(\"/regex--> b").unwrap_err().column,
+            9
+        );
+        assert_eq!(
+            parse("accept a").unwrap_err().message,
+            "missing `initial` declaration"
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Renders state machines as Graphviz DOT or Mermaid diagrams.
+
+use std::collections::HashMap;
+
+use crate::{models_raw, state_machine::StateMachine};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Dot,
+    Mermaid,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "dot" | "graphviz" => Some(Format::Dot),
+            "mermaid" => Some(Format::Mermaid),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Dot => "dot",
+            Format::Mermaid => "mmd",
+        }
+    }
+}
+
+pub fn render(state_machine: &StateMachine, format: Format) -> String {
+    match format {
+        Format::Dot => to_dot(state_machine),
+        Format::Mermaid => to_mermaid(state_machine),
+    }
+}
+
+/// Names of all the states of the machine, including the ones that are only
+/// mentioned as the initial state, by edges or by the list of accepting states.
+fn state_names(state_machine: &models_raw::StateMachine) -> Vec<&str> {
+    let mentioned = state_machine
+        .states
+        .iter()
+        .map(|state| state.name.as_str())
+        .chain(std::iter::once(state_machine.initial_state.as_str()))
+        .chain(
+            state_machine
+                .states
+                .iter()
+                .flat_map(|state| state.edges.iter())
+                .filter_map(|edge| edge.next.as_deref()),
+        )
+        .chain(state_machine.accepting_states.iter().map(String::as_str));
+
+    let mut names = vec![];
+    for name in mentioned {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+fn edge_label(edge: &models_raw::Edge, separator: &str) -> String {
+    std::iter::once(edge.prompt.to_string())
+        .chain(edge.actions.iter().map(|action| action.to_string()))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+fn dot_string(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+pub fn to_dot(state_machine: &StateMachine) -> String {
+    let raw = state_machine.to_raw();
+
+    let mut lines = vec![
+        "digraph riddle {".to_owned(),
+        "    rankdir=LR;".to_owned(),
+        "    __start [shape=point];".to_owned(),
+        format!("    __start -> {};", dot_string(&raw.initial_state)),
+    ];
+
+    for name in state_names(&raw) {
+        let shape = if raw.accepting_states.iter().any(|state| state == name) {
+            "doublecircle"
+        } else {
+            "circle"
+        };
+        lines.push(format!("    {} [shape={}];", dot_string(name), shape));
+    }
+
+    for state in &raw.states {
+        for edge in &state.edges {
+            let next = edge.next.as_ref().unwrap_or(&state.name);
+            lines.push(format!(
+                "    {} -> {} [label={}];",
+                dot_string(&state.name),
+                dot_string(next),
+                dot_string(&edge_label(edge, "\n"))
+            ));
+        }
+    }
+
+    lines.push("}".to_owned());
+    lines.join("\n") + "\n"
+}
+
+fn mermaid_string(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '#' => "#35;".to_owned(),
+            '"' => "#quot;".to_owned(),
+            ';' => "#59;".to_owned(),
+            '\n' => "<br/>".to_owned(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+pub fn to_mermaid(state_machine: &StateMachine) -> String {
+    let raw = state_machine.to_raw();
+
+    // Mermaid is picky about identifiers, so states are given generated ones
+    // and their names are used as labels.
+    let names = state_names(&raw);
+    let ids: HashMap<&str, String> = names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| (*name, format!("s{}", index)))
+        .collect();
+
+    let mut lines = vec!["stateDiagram-v2".to_owned()];
+    for name in &names {
+        lines.push(format!(
+            "    state \"{}\" as {}",
+            mermaid_string(name),
+            ids[name]
+        ));
+    }
+
+    lines.push(format!("    [*] --> {}", ids[raw.initial_state.as_str()]));
+    for name in &raw.accepting_states {
+        lines.push(format!("    {} --> [*]", ids[name.as_str()]));
+    }
+
+    for state in &raw.states {
+        for edge in &state.edges {
+            let next = edge.next.as_ref().unwrap_or(&state.name);
+            lines.push(format!(
+                "    {} --> {} : {}",
+                ids[state.name.as_str()],
+                ids[next.as_str()],
+                mermaid_string(&edge_label(edge, "\n"))
+            ));
+        }
+    }
+
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl;
+
+    fn example_state_machine() -> StateMachine {
+        StateMachine::new(
+            dsl::parse(
+                r#"
+                    initial start
+                    accept end
+                    start --"hello"--> end : say "Hi"
+                    start --*--> . : say "Say \"hello\""
+                "#,
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn dot() {
+        assert_eq!(
+            to_dot(&example_state_machine()),
+            r#"digraph riddle {
+    rankdir=LR;
+    __start [shape=point];
+    __start -> "start";
+    "start" [shape=circle];
+    "end" [shape=doublecircle];
+    "start" -> "end" [label="\"hello\"\nsay \"Hi\""];
+    "start" -> "start" [label="*\nsay \"Say \\\"hello\\\"\""];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn mermaid() {
+        assert_eq!(
+            to_mermaid(&example_state_machine()),
+            r#"stateDiagram-v2
+    state "start" as s0
+    state "end" as s1
+    [*] --> s0
+    s1 --> [*]
+    s0 --> s1 : #quot;hello#quot;<br/>say #quot;Hi#quot;
+    s0 --> s0 : *<br/>say #quot;Say \#quot;hello\#quot;#quot;
+"#
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! Ranks the players who solved a riddle, by time or by score.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use teloxide::types::UserId;
+
+// score of a solve without wrong answers and hints
+pub const MAX_SCORE: u32 = 1000;
+// points taken for every wrong answer, hints take their own penalty
+pub const WRONG_ANSWER_PENALTY: u32 = 10;
+
+/// A finished playthrough of a riddle.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Solve {
+    pub user: UserId,
+    // shown on the leaderboard
+    pub name: String,
+    // the group chat's title if the riddle was solved as a team, every
+    // member who answered gets a solve
+    pub team: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    // time spent on the riddle, without pauses
+    pub time: Duration,
+    pub answers: u32,
+    pub wrong_answers: u32,
+    pub hints: usize,
+    pub hint_penalty: u32,
+}
+
+pub fn score(wrong_answers: u32, hint_penalty: u32) -> u32 {
+    MAX_SCORE
+        .saturating_sub(hint_penalty)
+        .saturating_sub(wrong_answers.saturating_mul(WRONG_ANSWER_PENALTY))
+}
+
+impl Solve {
+    pub fn score(&self) -> u32 {
+        score(self.wrong_answers, self.hint_penalty)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ranking {
+    // the fastest first
+    Time,
+    // the highest score first
+    Score,
+}
+
+fn compare(a: &Solve, b: &Solve, ranking: Ranking) -> Ordering {
+    let by_time = a.time.cmp(&b.time);
+    let by_score = b.score().cmp(&a.score());
+    match ranking {
+        Ranking::Time => by_time.then(by_score),
+        Ranking::Score => by_score.then(by_time),
+    }
+    .then(a.finished_at.cmp(&b.finished_at))
+}
+
+/// The best solve of every player, best first. Ties go to whoever solved
+/// the riddle first.
+pub fn rank(solves: &[Solve], ranking: Ranking) -> Vec<&Solve> {
+    let mut best: HashMap<UserId, &Solve> = HashMap::new();
+    for solve in solves {
+        best.entry(solve.user)
+            .and_modify(|best| {
+                if compare(solve, best, ranking).is_lt() {
+                    *best = solve;
+                }
+            })
+            .or_insert(solve);
+    }
+
+    let mut ranked: Vec<&Solve> = best.into_values().collect();
+    ranked.sort_by(|a, b| compare(a, b, ranking));
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(user: u64, seconds: i64, wrong_answers: u32, hint_penalty: u32) -> Solve {
+        let started_at = DateTime::<Utc>::default();
+        Solve {
+            user: UserId(user),
+            name: user.to_string(),
+            team: None,
+            started_at,
+            finished_at: started_at + Duration::seconds(seconds),
+            time: Duration::seconds(seconds),
+            answers: wrong_answers + 1,
+            wrong_answers,
+            hints: 0,
+            hint_penalty,
+        }
+    }
+
+    fn names(ranked: Vec<&Solve>) -> Vec<&str> {
+        ranked.iter().map(|solve| solve.name.as_str()).collect()
+    }
+
+    #[test]
+    fn score() {
+        assert_eq!(solve(1, 60, 0, 0).score(), MAX_SCORE);
+        assert_eq!(solve(1, 60, 3, 20).score(), 950);
+        assert_eq!(solve(1, 60, 1000, 0).score(), 0);
+    }
+
+    #[test]
+    fn ranking() {
+        let solves = vec![
+            solve(1, 300, 0, 0),
+            solve(2, 60, 5, 0),
+            solve(3, 120, 0, 50),
+            // a second, faster solve of the first player
+            solve(1, 90, 1, 0),
+        ];
+
+        assert_eq!(names(rank(&solves, Ranking::Time)), vec!["2", "1", "3"]);
+        assert_eq!(rank(&solves, Ranking::Time)[1].time, Duration::seconds(90));
+        assert_eq!(names(rank(&solves, Ranking::Score)), vec!["1", "2", "3"]);
+        assert_eq!(
+            rank(&solves, Ranking::Score)[0].time,
+            Duration::seconds(300)
+        );
+        assert!(rank(&[], Ranking::Time).is_empty());
+    }
+}
@@ -0,0 +1,15 @@
+//! The riddle engine: state machine formats and their interpretation. It is
+//! shared by the bot and the offline tools in `src/bin`.
+
+pub mod dsl;
+pub mod graph;
+pub mod leaderboard;
+pub mod lints;
+pub mod models;
+pub mod models_raw;
+pub mod playthroughs;
+pub mod race;
+pub mod rate_limit;
+pub mod solver;
+pub mod state_machine;
+pub mod utils;
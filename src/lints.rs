@@ -0,0 +1,319 @@
+//! Checks state machines for mistakes. Errors make a state machine unusable,
+//! warnings point at parts of it that probably don't do what the author
+//! meant.
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+
+use crate::{
+    models_raw::{Prompt, StateMachine},
+    utils::Error,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Lint {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Lint {
+    fn error(message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+        }
+    }
+
+    fn warning(message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.message),
+            Severity::Warning => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+fn errors(state_machine: &StateMachine) -> Vec<Lint> {
+    let mut lints = vec![];
+    let mut defined = HashSet::new();
+
+    for state in &state_machine.states {
+        if !defined.insert(state.name.as_str()) {
+            lints.push(Lint::error(format!(
+                "state {} is defined more than once",
+                state.name
+            )));
+        }
+    }
+
+    if let Some(rate_limit) = &state_machine.rate_limit {
+        if rate_limit.requests == 0 || rate_limit.seconds == 0 {
+            lints.push(Lint::error(
+                "the rate limit must allow at least 1 answer in at least 1 second".to_owned(),
+            ));
+        }
+    }
+
+    if !defined.contains(state_machine.initial_state.as_str()) {
+        lints.push(Lint::error(format!(
+            "initial state {} is not defined",
+            state_machine.initial_state
+        )));
+    }
+
+    let is_target = |name: &String| {
+        defined.contains(name.as_str()) || state_machine.accepting_states.contains(name)
+    };
+
+    for state in &state_machine.states {
+        match (state.max_attempts, &state.failure_state) {
+            (Some(0), _) => lints.push(Lint::error(format!(
+                "state {} allows 0 attempts",
+                state.name
+            ))),
+            (Some(_), None) if state.lockout == 0 => lints.push(Lint::error(format!(
+                "state {} limits attempts, but has neither a failure state nor a lockout",
+                state.name
+            ))),
+            (_, Some(failure_state)) if !is_target(failure_state) => {
+                lints.push(Lint::error(format!(
+                    "failure state {} of state {} is not defined",
+                    failure_state, state.name
+                )))
+            }
+            _ => {}
+        }
+
+        for edge in &state.edges {
+            if let Prompt::Regex(regex) = &edge.prompt {
+                if let Err(e) = regex::Regex::new(regex) {
+                    lints.push(Lint::error(format!(
+                        "{}: invalid regex: {}",
+                        edge.describe(&state.name),
+                        e
+                    )));
+                }
+            }
+
+            // the riddle ends in accepting states, so they don't need edges
+            match &edge.next {
+                Some(next) if !is_target(next) => {
+                    lints.push(Lint::error(format!(
+                        "{}: state {} is not defined",
+                        edge.describe(&state.name),
+                        next
+                    )));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    lints
+}
+
+fn reachable_states(state_machine: &StateMachine) -> HashSet<&str> {
+    let mut reachable = HashSet::from([state_machine.initial_state.as_str()]);
+    let mut queue = VecDeque::from([state_machine.initial_state.as_str()]);
+
+    while let Some(name) = queue.pop_front() {
+        let Some(state) = state_machine.states.iter().find(|state| state.name == name) else {
+            continue;
+        };
+        let nexts = state
+            .edges
+            .iter()
+            .filter_map(|edge| edge.next.as_deref())
+            .chain(state.failure_state.as_deref());
+        for next in nexts {
+            if reachable.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+
+    reachable
+}
+
+fn warnings(state_machine: &StateMachine) -> Vec<Lint> {
+    let mut lints = vec![];
+    let reachable = reachable_states(state_machine);
+
+    for state in &state_machine.states {
+        let accepting = state_machine.accepting_states.contains(&state.name);
+
+        if !reachable.contains(state.name.as_str()) {
+            lints.push(Lint::warning(format!(
+                "state {} is unreachable",
+                state.name
+            )));
+        }
+        if state.checkpoint && state_machine.undo.is_none() {
+            lints.push(Lint::warning(format!(
+                "state {} is a checkpoint, but undo is not allowed",
+                state.name
+            )));
+        }
+        if state.max_attempts.is_none() && (state.failure_state.is_some() || state.lockout > 0) {
+            lints.push(Lint::warning(format!(
+                "state {} has a failure state or a lockout, but doesn't limit attempts",
+                state.name
+            )));
+        }
+        if !accepting && state.edges.is_empty() {
+            lints.push(Lint::warning(format!(
+                "state {} is a dead end: it is not accepting and has no edges",
+                state.name
+            )));
+        }
+        if accepting && !state.edges.is_empty() {
+            lints.push(Lint::warning(format!(
+                "state {} is accepting, so its edges never fire",
+                state.name
+            )));
+            continue;
+        }
+
+        // `apply` takes the first edge that matches
+        for (index, edge) in state.edges.iter().enumerate() {
+            let earlier = &state.edges[..index];
+            let shadowed_by = earlier.iter().find_map(|earlier_edge| {
+                match (&earlier_edge.prompt, &edge.prompt) {
+                    (Prompt::Either, _) => Some("an earlier `*` prompt"),
+                    (Prompt::Text(earlier_text), Prompt::Text(text)) if earlier_text == text => {
+                        Some("an earlier edge with the same text")
+                    }
+                    (Prompt::Regex(regex), Prompt::Text(text))
+                        if regex::Regex::new(regex).is_ok_and(|regex| regex.is_match(text)) =>
+                    {
+                        Some("an earlier regex")
+                    }
+                    _ => None,
+                }
+            });
+
+            if let Some(shadowed_by) = shadowed_by {
+                lints.push(Lint::warning(format!(
+                    "{}: never fires, shadowed by {}",
+                    edge.describe(&state.name),
+                    shadowed_by
+                )));
+            }
+        }
+    }
+
+    lints
+}
+
+/// Errors come first. Warnings are only reported for state machines without
+/// errors.
+pub fn check(state_machine: &StateMachine) -> Vec<Lint> {
+    let errors = errors(state_machine);
+    if errors.is_empty() {
+        warnings(state_machine)
+    } else {
+        errors
+    }
+}
+
+/// Fails with every error found. Building a state machine that has errors
+/// panics or leaves players stuck in states that don't exist.
+pub fn validate(state_machine: &StateMachine) -> Result<(), Error> {
+    let errors: Vec<String> = errors(state_machine)
+        .iter()
+        .map(|lint| lint.message.clone())
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n").into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl;
+
+    fn messages_of(state_machine: &StateMachine) -> Vec<String> {
+        check(state_machine).iter().map(Lint::to_string).collect()
+    }
+
+    fn messages(source: &str) -> Vec<String> {
+        messages_of(&dsl::parse(source).unwrap())
+    }
+
+    #[test]
+    fn errors() {
+        let mut state_machine = dsl::parse("initial a\na --\"x\"--> b").unwrap();
+        state_machine.initial_state = "z".to_string();
+        state_machine.states[0].edges[0].next = Some("c".to_string());
+        state_machine.states[0].edges[0].prompt = Prompt::Regex("(".to_string());
+
+        let lints = check(&state_machine);
+        assert_eq!(lints.len(), 3);
+        assert!(lints.iter().all(|lint| lint.severity == Severity::Error));
+        assert_eq!(lints[0].message, "initial state z is not defined");
+        assert!(validate(&state_machine).is_err());
+
+        let mut state_machine = dsl::parse("initial a\nattempts a 3 fail b").unwrap();
+        state_machine.states.pop();
+        assert_eq!(
+            messages_of(&state_machine),
+            vec!["error: failure state b of state a is not defined"]
+        );
+        state_machine.states[0].failure_state = None;
+        assert_eq!(
+            messages_of(&state_machine),
+            vec!["error: state a limits attempts, but has neither a failure state nor a lockout"]
+        );
+    }
+
+    #[test]
+    fn warnings() {
+        assert_eq!(
+            messages(
+                r#"
+                    initial a
+                    accept c
+                    a --"x"--> b
+                    a --"^x+$"/regex--> c
+                    a --"xx"--> c
+                    a --*--> .
+                    a --"y"--> c
+                    a --"y"--> b
+                    c --"z"--> a
+                    d --"w"--> a
+                "#
+            ),
+            vec![
+                "warning: a --\"xx\"--> c: never fires, shadowed by an earlier regex",
+                "warning: a --\"y\"--> c: never fires, shadowed by an earlier `*` prompt",
+                "warning: a --\"y\"--> b: never fires, shadowed by an earlier `*` prompt",
+                "warning: state c is accepting, so its edges never fire",
+                "warning: state b is a dead end: it is not accepting and has no edges",
+                "warning: state d is unreachable",
+            ]
+        );
+        assert!(messages("initial a\naccept b\na --\"x\"--> b").is_empty());
+        assert_eq!(
+            messages("initial a\naccept b\na --\"x\"--> b\ncheckpoint a"),
+            vec!["warning: state a is a checkpoint, but undo is not allowed"]
+        );
+        assert!(messages("initial a\naccept b\na --\"x\"--> b\ncheckpoint a\nundo").is_empty());
+    }
+}
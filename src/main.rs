@@ -1,12 +1,14 @@
 use teloxide::prelude::*;
 
+use riddle_bot::{
+    graph, leaderboard, lints, models, models_raw, playthroughs, race, rate_limit, solver,
+    state_machine, utils,
+};
+
 mod admin_commands;
 mod commands;
-mod models;
-mod models_raw;
 mod riddles;
-mod state_machine;
-mod utils;
+mod throttling;
 
 #[tokio::main]
 async fn main() {
@@ -21,6 +23,7 @@ async fn main() {
     let mut dependencies = riddles::dependencies();
     dependencies.insert_container(admin_commands::dependencies());
     dependencies.insert_container(commands::dependencies());
+    dependencies.insert_container(throttling::dependencies());
 
     Dispatcher::builder(bot, handler)
         .dependencies(dependencies)
@@ -3,14 +3,14 @@ use teloxide::types::ChatId;
 
 use crate::{models_raw, utils::HandlerResult};
 
-pub(crate) enum Prompt {
+pub enum Prompt {
     Text(String),
     Regex(regex::Regex),
     Either,
 }
 
 impl Prompt {
-    pub(crate) fn new(prompt: models_raw::Prompt) -> Self {
+    pub fn new(prompt: models_raw::Prompt) -> Self {
         match prompt {
             models_raw::Prompt::Text(text) => Prompt::Text(text),
             models_raw::Prompt::Regex(regex) => Prompt::Regex(regex::Regex::new(&regex).unwrap()),
@@ -18,7 +18,15 @@ impl Prompt {
         }
     }
 
-    pub(crate) fn matches(&self, input: &str) -> bool {
+    pub fn to_raw(&self) -> models_raw::Prompt {
+        match self {
+            Prompt::Text(text) => models_raw::Prompt::Text(text.clone()),
+            Prompt::Regex(regex) => models_raw::Prompt::Regex(regex.as_str().to_owned()),
+            Prompt::Either => models_raw::Prompt::Either,
+        }
+    }
+
+    pub fn matches(&self, input: &str) -> bool {
         match self {
             Prompt::Text(text) => text == input,
             Prompt::Regex(regex) => regex.is_match(input),
@@ -27,47 +35,91 @@ impl Prompt {
     }
 }
 
-pub(crate) struct Edge {
+pub struct Edge {
     pub prompt: Prompt,
     pub actions: Vec<Action>,
     pub next: Option<String>,
 }
 
 impl Edge {
-    pub(crate) fn new(edge: models_raw::Edge) -> Self {
+    pub fn new(edge: models_raw::Edge) -> Self {
         Self {
             prompt: Prompt::new(edge.prompt),
             actions: edge.actions.into_iter().map(Action::new).collect(),
             next: edge.next,
         }
     }
+
+    /// Whether this is a `*` edge that stays in `from`, the usual feedback
+    /// for wrong answers.
+    pub fn is_catch_all_loop(&self, from: &str) -> bool {
+        matches!(self.prompt, Prompt::Either)
+            && self.next.as_deref().is_none_or(|next| next == from)
+    }
+
+    pub fn to_raw(&self) -> models_raw::Edge {
+        models_raw::Edge {
+            prompt: self.prompt.to_raw(),
+            actions: self.actions.iter().map(Action::to_raw).collect(),
+            next: self.next.clone(),
+        }
+    }
 }
 
-pub(crate) struct State {
+#[derive(Default)]
+pub struct State {
     pub edges: Vec<Edge>,
+    pub description: Option<String>,
+    pub hints: Vec<models_raw::Hint>,
+    pub max_attempts: Option<u32>,
+    pub failure_state: Option<String>,
+    pub lockout: u32,
+    pub checkpoint: bool,
+    pub milestone: bool,
 }
 
 impl State {
-    pub(crate) fn new(state: models_raw::State) -> Self {
+    pub fn new(state: models_raw::State) -> Self {
         Self {
             edges: state.edges.into_iter().map(Edge::new).collect(),
+            description: state.description,
+            hints: state.hints,
+            max_attempts: state.max_attempts,
+            failure_state: state.failure_state,
+            lockout: state.lockout,
+            checkpoint: state.checkpoint,
+            milestone: state.milestone,
+        }
+    }
+
+    pub fn to_raw(&self, name: &str) -> models_raw::State {
+        models_raw::State {
+            name: name.to_owned(),
+            edges: self.edges.iter().map(Edge::to_raw).collect(),
+            description: self.description.clone(),
+            hints: self.hints.clone(),
+            max_attempts: self.max_attempts,
+            failure_state: self.failure_state.clone(),
+            lockout: self.lockout,
+            checkpoint: self.checkpoint,
+            milestone: self.milestone,
         }
     }
 }
 
-pub(crate) enum Action {
+pub enum Action {
     Message(String),
     SendTo(ChatId, String),
 }
 
 #[async_trait]
-pub(crate) trait ActionApplier {
+pub trait ActionApplier {
     async fn apply_message(&mut self, message: &str) -> HandlerResult;
     async fn apply_send_to(&mut self, chat_id: ChatId, message: &str) -> HandlerResult;
 }
 
 impl Action {
-    pub(crate) fn new(action: models_raw::Action) -> Self {
+    pub fn new(action: models_raw::Action) -> Self {
         match action {
             models_raw::Action::Message(message) => Action::Message(message),
             models_raw::Action::SendTo { chat_id, message } => {
@@ -76,7 +128,17 @@ impl Action {
         }
     }
 
-    pub(crate) async fn apply(&self, applier: &mut impl ActionApplier) -> HandlerResult {
+    pub fn to_raw(&self) -> models_raw::Action {
+        match self {
+            Action::Message(message) => models_raw::Action::Message(message.clone()),
+            Action::SendTo(chat_id, message) => models_raw::Action::SendTo {
+                chat_id: chat_id.0,
+                message: message.clone(),
+            },
+        }
+    }
+
+    pub async fn apply(&self, applier: &mut impl ActionApplier) -> HandlerResult {
         match self {
             Action::Message(message) => applier.apply_message(message).await,
             Action::SendTo(chat_id, message) => applier.apply_send_to(*chat_id, message).await,
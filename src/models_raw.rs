@@ -1,48 +1,295 @@
-use serde::Deserialize;
+use std::fmt;
 
-#[derive(Deserialize, Debug, PartialEq)]
+use serde::{Deserialize, Serialize};
+
+use crate::{dsl, utils::Error};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(
     deny_unknown_fields,
     rename_all = "snake_case",
     tag = "type",
     content = "content"
 )]
-pub(crate) enum Prompt {
+pub enum Prompt {
     Text(String),
     Regex(String),
     Either,
 }
 
-#[derive(Deserialize, Debug, PartialEq)]
-pub(crate) struct Edge {
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Edge {
     pub prompt: Prompt,
     pub actions: Vec<Action>,
     pub next: Option<String>,
 }
 
-#[derive(Deserialize, Debug, PartialEq)]
-pub(crate) struct State {
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// A hint players can ask for with `/hint`, revealed in order.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Hint {
+    pub text: String,
+    // seconds the player has to wait after entering the state, or after the
+    // previous hint, before this one is revealed
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub cooldown: u32,
+    // points taken from the player's score
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub penalty: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct State {
     pub name: String,
     pub edges: Vec<Edge>,
+    // shown to players by /status, e.g. "You are in the library"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hints: Vec<Hint>,
+    // wrong answers allowed in this state, unlimited if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+    // where players go when they run out of attempts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_state: Option<String>,
+    // seconds players can't answer for when they run out of attempts
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub lockout: u32,
+    // players who reach this state can't undo the answers that led here
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub checkpoint: bool,
+    // announced in races when a team reaches it, see `crate::race`
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub milestone: bool,
 }
 
-#[derive(Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(
     deny_unknown_fields,
     rename_all = "snake_case",
     tag = "type",
     content = "content"
 )]
-pub(crate) enum Action {
+pub enum Action {
     Message(String),
     SendTo { chat_id: i64, message: String },
 }
 
-#[derive(Deserialize, Debug, PartialEq)]
-pub(crate) struct StateMachine {
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Step {
+    pub input: String,
+    // messages expected after this input, not checked if missing, an empty
+    // list expects no messages at all
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub messages: Option<Vec<String>>,
+}
+
+/// An example playthrough of a riddle, checked before the riddle is saved.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Playthrough {
+    pub name: String,
+    pub steps: Vec<Step>,
+    pub final_state: String,
+    pub accepts: bool,
+}
+
+/// At most `requests` requests every `seconds` seconds, see
+/// [`crate::rate_limit`].
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub seconds: u32,
+}
+
+/// Lets players take back their answers with `/undo`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Undo {
+    // undos allowed per playthrough, unlimited if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+/// Who can see the riddle's leaderboard. Its creator and admins always can.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaderboardVisibility {
+    #[default]
+    Public,
+    // only players who solved the riddle
+    Solvers,
+    Hidden,
+}
+
+/// Whether the riddle is played alone, by a group chat as a team, or both.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayMode {
+    Solo,
+    Team,
+    #[default]
+    Both,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct StateMachine {
     pub initial_state: String,
     pub accepting_states: Vec<String>,
     pub states: Vec<State>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tests: Vec<Playthrough>,
+    // limits answers in this riddle instead of the bot's global limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
+    // `/undo` is not allowed if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undo: Option<Undo>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub leaderboard: LeaderboardVisibility,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub play_mode: PlayMode,
+}
+
+/// Writes a string literal in the notation of the text format.
+fn write_literal(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Prompt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Prompt::Text(text) => write_literal(f, text),
+            Prompt::Regex(regex) => {
+                write_literal(f, regex)?;
+                write!(f, "/regex")
+            }
+            Prompt::Either => write!(f, "*"),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Message(message) => {
+                write!(f, "say ")?;
+                write_literal(f, message)
+            }
+            Action::SendTo { chat_id, message } => {
+                write!(f, "send {} ", chat_id)?;
+                write_literal(f, message)
+            }
+        }
+    }
+}
+
+/// Writes a state name in the notation of the text format, quoted unless it
+/// is a word.
+fn write_state_name(f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+    if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        write!(f, "{}", name)
+    } else {
+        write_literal(f, name)
+    }
+}
+
+/// Writes the playthrough as a test block of the text format.
+impl fmt::Display for Playthrough {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "test ")?;
+        write_literal(f, &self.name)?;
+        writeln!(f)?;
+        for step in &self.steps {
+            write!(f, "> ")?;
+            write_literal(f, &step.input)?;
+            writeln!(f)?;
+            if step.messages.as_ref().is_some_and(Vec::is_empty) {
+                writeln!(f, "< (none)")?;
+            }
+            for message in step.messages.iter().flatten() {
+                write!(f, "< ")?;
+                write_literal(f, message)?;
+                writeln!(f)?;
+            }
+        }
+        write!(
+            f,
+            "expect {} ",
+            if self.accepts { "accept" } else { "reject" }
+        )?;
+        write_state_name(f, &self.final_state)
+    }
+}
+
+impl Edge {
+    /// Describes the edge in the notation of the text format.
+    pub fn describe(&self, from: &str) -> String {
+        let mut description = format!(
+            "{} --{}--> {}",
+            from,
+            self.prompt,
+            self.next.as_deref().unwrap_or(".")
+        );
+        if !self.actions.is_empty() {
+            let actions: Vec<String> = self.actions.iter().map(Action::to_string).collect();
+            description += &format!(" : {}", actions.join(", "));
+        }
+        description
+    }
+}
+
+/// A full riddle definition, as exported by `/exportriddle`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Riddle {
+    pub name: String,
+    pub description: String,
+    pub state_machine: StateMachine,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Text,
+}
+
+impl Format {
+    /// Guesses the format from the file name, if there is one, and falls back
+    /// to looking at the source itself.
+    pub fn detect(file_name: Option<&str>, source: &str) -> Self {
+        let extension = file_name
+            .and_then(|name| name.rsplit_once('.'))
+            .map(|(_, extension)| extension.to_lowercase());
+
+        match extension.as_deref() {
+            Some("json") => Format::Json,
+            Some("riddle") | Some("txt") => Format::Text,
+            _ if source.trim_start().starts_with('{') => Format::Json,
+            _ => Format::Text,
+        }
+    }
+}
+
+/// Parses a state machine written either in JSON or in the text format
+/// described in [`dsl`].
+pub fn parse_state_machine(source: &str, format: Format) -> Result<StateMachine, Error> {
+    match format {
+        Format::Json => Ok(serde_json::from_str(source)?),
+        Format::Text => Ok(dsl::parse(source)?),
+    }
 }
 
 #[cfg(test)]
@@ -68,6 +315,7 @@ mod tests {
                             next: Some("2".to_string()),
                         },
                     ],
+                    ..Default::default()
                 },
                 State {
                     name: "1".to_string(),
@@ -76,12 +324,14 @@ mod tests {
                         actions: vec![Action::Message("Nope".to_string())],
                         next: Some("0".to_string()),
                     }],
+                    ..Default::default()
                 },
                 State {
                     name: "2".to_string(),
-                    edges: vec![],
+                    ..Default::default()
                 },
             ],
+            ..Default::default()
         }
     }
 
@@ -151,4 +401,15 @@ mod tests {
 
         assert_eq!(state_machine, example_state_machine());
     }
+
+    #[test]
+    fn format_detection() {
+        assert_eq!(
+            Format::detect(Some("riddle.JSON"), "initial a"),
+            Format::Json
+        );
+        assert_eq!(Format::detect(Some("riddle.riddle"), "{"), Format::Text);
+        assert_eq!(Format::detect(Some("riddle"), "  {}"), Format::Json);
+        assert_eq!(Format::detect(None, "initial a"), Format::Text);
+    }
 }
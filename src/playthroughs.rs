@@ -0,0 +1,164 @@
+//! Runs the example playthroughs embedded in riddle definitions.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use teloxide::types::ChatId;
+
+use crate::{
+    models::ActionApplier, models_raw::Playthrough, state_machine::StateMachine,
+    utils::HandlerResult,
+};
+
+/// Collects the messages that would be sent, wherever they would go.
+#[derive(Default)]
+pub struct RecordingApplier {
+    pub messages: Vec<String>,
+}
+
+#[async_trait]
+impl ActionApplier for RecordingApplier {
+    async fn apply_message(&mut self, message: &str) -> HandlerResult {
+        self.messages.push(message.to_string());
+        Ok(())
+    }
+
+    async fn apply_send_to(&mut self, _chat_id: ChatId, message: &str) -> HandlerResult {
+        self.messages.push(message.to_string());
+        Ok(())
+    }
+}
+
+/// Plays the playthrough and describes the first way in which it went
+/// differently than expected, if any.
+pub async fn run(
+    state_machine: &StateMachine,
+    playthrough: &Playthrough,
+) -> HandlerResult<Option<String>> {
+    let mut state = state_machine.initial_state.clone();
+    let mut attempts = HashMap::new();
+
+    for (index, step) in playthrough.steps.iter().enumerate() {
+        let step_number = index + 1;
+        if state_machine.is_accepting(&state) {
+            return Ok(Some(format!(
+                "the riddle is already solved before step {}",
+                step_number
+            )));
+        }
+        if !state_machine.has_state(&state) {
+            return Ok(Some(format!(
+                "state {} reached before step {} is not defined",
+                state, step_number
+            )));
+        }
+
+        let mut applier = RecordingApplier::default();
+        // lockouts don't change the state, so they are ignored here
+        state = state_machine
+            .answer(&mut applier, &state, &step.input, &mut attempts)
+            .await?
+            .state;
+
+        if let Some(expected) = &step.messages {
+            if *expected != applier.messages {
+                return Ok(Some(format!(
+                    "step {} ({:?}): expected messages {:?}, got {:?}",
+                    step_number, step.input, expected, applier.messages
+                )));
+            }
+        }
+    }
+
+    if state != playthrough.final_state {
+        return Ok(Some(format!(
+            "expected to end in state {}, ended in {}",
+            playthrough.final_state, state
+        )));
+    }
+    if state_machine.is_accepting(&state) != playthrough.accepts {
+        return Ok(Some(if playthrough.accepts {
+            format!("state {} is not accepting", state)
+        } else {
+            format!("state {} is accepting", state)
+        }));
+    }
+
+    Ok(None)
+}
+
+/// Runs all the playthroughs of the state machine, returning a line for
+/// every failed one.
+pub async fn run_all(state_machine: &StateMachine) -> HandlerResult<Vec<String>> {
+    let mut failures = vec![];
+    for playthrough in state_machine.tests() {
+        if let Some(failure) = run(state_machine, playthrough).await? {
+            failures.push(format!("{}: {}", playthrough.name, failure));
+        }
+    }
+    Ok(failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl;
+
+    #[tokio::test]
+    async fn playthroughs() {
+        let state_machine = StateMachine::new(
+            dsl::parse(
+                r#"
+                    initial a
+                    accept c
+                    a --"x"--> b : say "to b"
+                    b --"y"--> c : say "to c", send 1 "solved"
+
+                    test "solves"
+                    > "x"
+                    < "to b"
+                    > "y"
+                    < "to c"
+                    < "solved"
+                    expect accept c
+
+                    test "stuck"
+                    > "y"
+                    < "nothing"
+                    expect reject a
+
+                    test "too long"
+                    > "x"
+                    > "y"
+                    > "z"
+                    expect accept c
+
+                    test "wrong end"
+                    > "x"
+                    expect accept b
+
+                    test "silent"
+                    > "z"
+                    < (none)
+                    expect reject a
+
+                    test "not silent"
+                    > "x"
+                    < (none)
+                    expect reject b
+                "#,
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(
+            run_all(&state_machine).await.unwrap(),
+            vec![
+                "stuck: step 1 (\"y\"): expected messages [\"nothing\"], got []".to_string(),
+                "too long: the riddle is already solved before step 3".to_string(),
+                "wrong end: state b is not accepting".to_string(),
+                "not silent: step 1 (\"x\"): expected messages [], got [\"to b\"]".to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Races between teams that play the same riddle from the same moment.
+
+use std::cmp::Ordering;
+
+use chrono::{DateTime, Duration, Utc};
+use teloxide::types::ChatId;
+
+/// An answer that moved a team to another state.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transition {
+    pub from: String,
+    pub to: String,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Team {
+    // the group chat the team plays in
+    pub chat: ChatId,
+    pub name: String,
+    pub transitions: Vec<Transition>,
+    // milestone states reached and when, in the order they were reached
+    pub milestones: Vec<(String, DateTime<Utc>)>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl Team {
+    fn last_milestone(&self) -> Option<DateTime<Utc>> {
+        self.milestones.last().map(|&(_, at)| at)
+    }
+}
+
+/// What a transition means for the race, worth announcing.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Progress {
+    // the number of milestones the team has reached so far
+    Milestone(usize),
+    // the team's place among the teams that finished
+    Finished(usize),
+}
+
+#[derive(Clone, Debug)]
+pub struct Race {
+    pub riddle: String,
+    // the riddle's name when the race started
+    pub name: String,
+    // where progress and the final standings are posted
+    pub announcements: ChatId,
+    pub started_at: DateTime<Utc>,
+    pub teams: Vec<Team>,
+}
+
+impl Race {
+    pub fn new(
+        riddle: String,
+        name: String,
+        announcements: ChatId,
+        teams: Vec<(ChatId, String)>,
+        started_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            riddle,
+            name,
+            announcements,
+            started_at,
+            teams: teams
+                .into_iter()
+                .map(|(chat, name)| Team {
+                    chat,
+                    name,
+                    transitions: vec![],
+                    milestones: vec![],
+                    finished_at: None,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn team(&self, chat: ChatId) -> Option<&Team> {
+        self.teams.iter().find(|team| team.chat == chat)
+    }
+
+    /// Records a transition of the team playing in `chat`. Answers that
+    /// don't change the state and answers after the team finished are not
+    /// recorded.
+    pub fn record(
+        &mut self,
+        chat: ChatId,
+        transition: Transition,
+        milestone: bool,
+        accepting: bool,
+    ) -> Option<Progress> {
+        let finished = self
+            .teams
+            .iter()
+            .filter(|team| team.finished_at.is_some())
+            .count();
+        let team = self.teams.iter_mut().find(|team| team.chat == chat)?;
+        if team.finished_at.is_some() || transition.from == transition.to {
+            return None;
+        }
+
+        let at = transition.at;
+        let to = transition.to.clone();
+        team.transitions.push(transition);
+        if accepting {
+            team.finished_at = Some(at);
+            Some(Progress::Finished(finished + 1))
+        } else if milestone && !team.milestones.iter().any(|(state, _)| *state == to) {
+            team.milestones.push((to, at));
+            Some(Progress::Milestone(team.milestones.len()))
+        } else {
+            None
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.teams.iter().all(|team| team.finished_at.is_some())
+    }
+
+    /// Time since the start of the race.
+    pub fn elapsed(&self, at: DateTime<Utc>) -> Duration {
+        at - self.started_at
+    }
+
+    /// The teams that finished by their finish time, then the others by the
+    /// milestones they reached and how early they reached the last one.
+    pub fn standings(&self) -> Vec<&Team> {
+        let mut teams: Vec<&Team> = self.teams.iter().collect();
+        teams.sort_by(|a, b| match (a.finished_at, b.finished_at) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => b.milestones.len().cmp(&a.milestones.len()).then_with(|| {
+                match (a.last_milestone(), b.last_milestone()) {
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    // teams that got nowhere come last
+                    _ => a.transitions.is_empty().cmp(&b.transitions.is_empty()),
+                }
+            }),
+        });
+        teams
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::<Utc>::default() + Duration::seconds(seconds)
+    }
+
+    fn transition(from: &str, to: &str, seconds: i64) -> Transition {
+        Transition {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            at: at(seconds),
+        }
+    }
+
+    fn race() -> Race {
+        Race::new(
+            "code".to_owned(),
+            "Riddle".to_owned(),
+            ChatId(100),
+            vec![
+                (ChatId(1), "one".to_owned()),
+                (ChatId(2), "two".to_owned()),
+                (ChatId(3), "three".to_owned()),
+            ],
+            at(0),
+        )
+    }
+
+    fn names(race: &Race) -> Vec<&str> {
+        race.standings()
+            .iter()
+            .map(|team| team.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn record() {
+        let mut race = race();
+
+        assert_eq!(
+            race.record(ChatId(1), transition("a", "b", 10), true, false),
+            Some(Progress::Milestone(1))
+        );
+        // milestones count once, wrong answers are not recorded
+        assert_eq!(
+            race.record(ChatId(1), transition("b", "b", 15), true, false),
+            None
+        );
+        assert_eq!(
+            race.record(ChatId(1), transition("c", "b", 20), true, false),
+            None
+        );
+        assert_eq!(
+            race.record(ChatId(2), transition("a", "end", 30), false, true),
+            Some(Progress::Finished(1))
+        );
+        assert_eq!(
+            race.record(ChatId(1), transition("b", "end", 40), false, true),
+            Some(Progress::Finished(2))
+        );
+        assert_eq!(
+            race.record(ChatId(1), transition("end", "a", 50), false, false),
+            None
+        );
+        assert_eq!(
+            race.record(ChatId(4), transition("a", "b", 60), false, false),
+            None
+        );
+
+        assert_eq!(race.team(ChatId(1)).unwrap().transitions.len(), 3);
+        assert!(!race.is_over());
+        race.record(ChatId(3), transition("a", "end", 70), false, true);
+        assert!(race.is_over());
+    }
+
+    #[test]
+    fn standings() {
+        let mut race = race();
+        assert_eq!(names(&race), vec!["one", "two", "three"]);
+
+        race.record(ChatId(3), transition("a", "b", 10), false, false);
+        race.record(ChatId(1), transition("a", "m", 20), true, false);
+        assert_eq!(names(&race), vec!["one", "three", "two"]);
+
+        race.record(ChatId(2), transition("a", "m", 15), true, false);
+        assert_eq!(names(&race), vec!["two", "one", "three"]);
+
+        // moving on without reaching another milestone keeps the place
+        race.record(ChatId(2), transition("m", "n", 25), false, false);
+        assert_eq!(names(&race), vec!["two", "one", "three"]);
+
+        race.record(ChatId(3), transition("b", "end", 30), false, true);
+        assert_eq!(names(&race), vec!["three", "two", "one"]);
+    }
+}
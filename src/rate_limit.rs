@@ -0,0 +1,120 @@
+//! Token buckets that limit how often players can do something, e.g. answer.
+
+use std::time::{Duration, Instant};
+
+use crate::models_raw::RateLimit;
+
+/// Parses a limit written as `REQUESTS/SECONDS`, e.g. `5/60`.
+pub fn parse_limit(text: &str) -> Option<RateLimit> {
+    let (requests, seconds) = text.trim().split_once('/')?;
+    let limit = RateLimit {
+        requests: requests.trim().parse().ok()?,
+        seconds: seconds.trim().parse().ok()?,
+    };
+    (limit.requests > 0 && limit.seconds > 0).then_some(limit)
+}
+
+/// Holds up to `limit.requests` tokens, which are refilled evenly over
+/// `limit.seconds`. Every request takes a token.
+pub struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+    // when the bucket is full again if no more tokens are taken
+    full_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn full(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.requests.into(),
+            updated_at: now,
+            full_at: now,
+        }
+    }
+
+    /// Whether the bucket refilled completely, so that it can be dropped and
+    /// created full again when needed.
+    pub fn is_full(&self, now: Instant) -> bool {
+        now >= self.full_at
+    }
+
+    /// Takes a token, or tells how long it takes until there is one.
+    pub fn take(&mut self, limit: &RateLimit, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(limit.requests);
+        let per_second = capacity / f64::from(limit.seconds);
+
+        let elapsed = now.saturating_duration_since(self.updated_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * per_second).min(capacity);
+        self.updated_at = now;
+
+        let taken = if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / per_second))
+        };
+        self.full_at = now + Duration::from_secs_f64((capacity - self.tokens) / per_second);
+        taken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        assert_eq!(
+            parse_limit(" 5 / 60"),
+            Some(RateLimit {
+                requests: 5,
+                seconds: 60,
+            })
+        );
+        assert_eq!(parse_limit("0/60"), None);
+        assert_eq!(parse_limit("5"), None);
+    }
+
+    #[test]
+    fn token_bucket() {
+        let limit = RateLimit {
+            requests: 2,
+            seconds: 10,
+        };
+        let start = Instant::now();
+        let mut bucket = TokenBucket::full(&limit, start);
+
+        assert!(bucket.take(&limit, start).is_ok());
+        assert!(bucket.take(&limit, start).is_ok());
+        assert_eq!(bucket.take(&limit, start), Err(Duration::from_secs(5)));
+
+        // a token every 5 seconds
+        let later = start + Duration::from_secs(6);
+        assert!(bucket.take(&limit, later).is_ok());
+        assert!(bucket.take(&limit, later).is_err());
+
+        // never more than the limit
+        let much_later = later + Duration::from_secs(3600);
+        for _ in 0..2 {
+            assert!(bucket.take(&limit, much_later).is_ok());
+        }
+        assert!(bucket.take(&limit, much_later).is_err());
+    }
+
+    #[test]
+    fn token_bucket_refill() {
+        let limit = RateLimit {
+            requests: 2,
+            seconds: 10,
+        };
+        let start = Instant::now();
+        let mut bucket = TokenBucket::full(&limit, start);
+        assert!(bucket.is_full(start));
+
+        assert!(bucket.take(&limit, start).is_ok());
+        assert!(bucket.take(&limit, start).is_ok());
+        assert!(bucket.take(&limit, start).is_err());
+        assert!(!bucket.is_full(start + Duration::from_secs(9)));
+        assert!(bucket.is_full(start + Duration::from_secs(10)));
+    }
+}
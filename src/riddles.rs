@@ -1,35 +1,525 @@
-use std::{collections::HashMap, sync::Arc};
+use chrono::{DateTime, Duration, Utc};
+use std::{collections::HashMap, fmt, sync::Arc};
 use teloxide::{dptree::di::Injectable, prelude::*};
 use tokio::sync::Mutex;
 
-use crate::state_machine;
+use crate::{
+    leaderboard, models_raw, race,
+    state_machine::{self, StateMachine},
+};
+
+/// An answer a player gave, see `ChatState::history`.
+#[derive(Clone, Debug)]
+pub(crate) struct Move {
+    pub input: String,
+    pub from: String,
+    pub to: String,
+    // who answered, missing for messages without a sender
+    pub user: Option<UserId>,
+    // the player ran out of attempts with this answer, see
+    // `StateMachine::count_attempt`
+    pub out_of_attempts: bool,
+}
+
+/// Why `ChatState::undo` didn't take an answer back.
+#[derive(Debug, PartialEq)]
+pub(crate) enum UndoError {
+    NotAllowed,
+    NoUndosLeft,
+    Checkpoint,
+    // the last step was to the failure state of running out of attempts
+    OutOfAttempts,
+    NothingToUndo,
+}
+
+/// A group member who answered in a team session.
+#[derive(Clone, Debug)]
+pub(crate) struct Member {
+    pub name: String,
+    pub answers: u32,
+    pub wrong_answers: u32,
+}
 
 #[derive(Clone, Debug)]
 pub(crate) struct ChatState {
     pub riddle: String,
+    // players stay on the version they started with until they finish
+    pub version: usize,
     pub state: String,
+    // playtest started with /testriddle, see `commands::SandboxApplier`
+    pub testing: bool,
+    // played by a group chat together, started with /teamriddle
+    pub team: bool,
+    // everyone who answered, with their share of the answers
+    pub members: HashMap<UserId, Member>,
+    pub started_at: DateTime<Utc>,
+    pub answers: u32,
+    // number of hints revealed in each state
+    pub hints: HashMap<String, usize>,
+    pub hint_penalty: u32,
+    // when the player entered the current state or got its last hint, hint
+    // cooldowns count from here
+    pub hint_clock: DateTime<Utc>,
+    // wrong answers per state since the player last ran out of attempts there
+    pub attempts: HashMap<String, u32>,
+    pub wrong_answers: u32,
+    // set when the player ran out of attempts in a state with a lockout
+    pub locked_until: Option<DateTime<Utc>>,
+    // set while the session is paused with /pause
+    pub paused_at: Option<DateTime<Utc>>,
+    // time spent paused, not counted as time spent on the riddle
+    pub paused_for: Duration,
+    // sessions expire after a while without answers, see
+    // `commands::expire_sessions`
+    pub last_active: DateTime<Utc>,
+    // answers that led to the current state, undone answers are removed
+    pub history: Vec<Move>,
+    pub undos: u32,
 }
 
-pub(crate) struct Riddle {
+impl ChatState {
+    pub(crate) fn new(
+        riddle: String,
+        version: usize,
+        state: String,
+        testing: bool,
+        team: bool,
+    ) -> Self {
+        Self {
+            riddle,
+            version,
+            state,
+            testing,
+            team,
+            members: HashMap::new(),
+            started_at: Utc::now(),
+            answers: 0,
+            hints: HashMap::new(),
+            hint_penalty: 0,
+            hint_clock: Utc::now(),
+            attempts: HashMap::new(),
+            wrong_answers: 0,
+            locked_until: None,
+            paused_at: None,
+            paused_for: Duration::zero(),
+            last_active: Utc::now(),
+            history: vec![],
+            undos: 0,
+        }
+    }
+
+    /// The same riddle and version from the initial state. Only the position
+    /// is reset: time, answers, attempts, penalties and a lockout carry over,
+    /// so starting over doesn't wipe them.
+    pub(crate) fn restart(&self, initial_state: String) -> Self {
+        let mut restarted = self.clone();
+        restarted.history.clear();
+        restarted.move_to(initial_state);
+        restarted
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    /// Time spent on the riddle, without the pauses.
+    pub(crate) fn time_spent(&self, now: DateTime<Utc>) -> Duration {
+        let paused_for = self.paused_for + self.paused_at.map_or(Duration::zero(), |at| now - at);
+        now - self.started_at - paused_for
+    }
+
+    /// Moves the player to `state`, restarting the hint cooldown if it is a
+    /// different state.
+    pub(crate) fn move_to(&mut self, state: String) {
+        if state != self.state {
+            self.state = state;
+            self.hint_clock = Utc::now();
+        }
+    }
+
+    pub(crate) fn hints_used(&self) -> usize {
+        self.hints.values().sum()
+    }
+
+    /// Takes back the last answer that changed the state, along with the
+    /// wrong answers given after it, if the riddle allows it. Attempts stay
+    /// counted. Answers can't be undone past a checkpoint or a move to a
+    /// failure state after running out of attempts.
+    pub(crate) fn undo(&mut self, state_machine: &StateMachine) -> Result<(), UndoError> {
+        let undo = state_machine.undo().ok_or(UndoError::NotAllowed)?;
+        if undo.limit.is_some_and(|limit| self.undos >= limit) {
+            return Err(UndoError::NoUndosLeft);
+        }
+        if state_machine.is_checkpoint(&self.state) {
+            return Err(UndoError::Checkpoint);
+        }
+        let index = self
+            .history
+            .iter()
+            .rposition(|step| step.from != step.to)
+            .ok_or(UndoError::NothingToUndo)?;
+        if self.history[index].out_of_attempts {
+            return Err(UndoError::OutOfAttempts);
+        }
+
+        let from = self.history[index].from.clone();
+        self.history.truncate(index);
+        self.move_to(from);
+        self.undos += 1;
+        Ok(())
+    }
+
+    /// The answers that led to the current state, as a test block that can
+    /// be replayed with `riddle_sim --test`.
+    pub(crate) fn transcript(&self) -> models_raw::Playthrough {
+        models_raw::Playthrough {
+            name: "transcript".to_owned(),
+            steps: self
+                .history
+                .iter()
+                .map(|step| models_raw::Step {
+                    input: step.input.clone(),
+                    messages: None,
+                })
+                .collect(),
+            final_state: self.state.clone(),
+            // finished sessions are ended
+            accepts: false,
+        }
+    }
+}
+
+/// The riddles a chat, or a member playing on their own in a group chat, is
+/// playing. Answers go to the focused one.
+#[derive(Clone, Default, Debug)]
+pub(crate) struct Sessions {
+    // in the order they were started
+    sessions: Vec<ChatState>,
+    // riddle code of the focused session
+    focused: Option<String>,
+}
+
+impl Sessions {
+    pub(crate) fn focused(&self) -> Option<&ChatState> {
+        self.focused.as_ref().and_then(|code| self.get(code))
+    }
+
+    pub(crate) fn get(&self, code: &str) -> Option<&ChatState> {
+        self.sessions.iter().find(|session| session.riddle == code)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &ChatState> {
+        self.sessions.iter()
+    }
+
+    pub(crate) fn is_focused(&self, code: &str) -> bool {
+        self.focused.as_deref() == Some(code)
+    }
+
+    /// Focuses the most recently started session that isn't paused.
+    fn refocus(&mut self) -> Option<&ChatState> {
+        self.focused = self
+            .sessions
+            .iter()
+            .rev()
+            .find(|session| !session.is_paused())
+            .map(|session| session.riddle.clone());
+        self.focused()
+    }
+
+    /// Adds a new session and focuses it.
+    pub(crate) fn start(&mut self, session: ChatState) {
+        self.focused = Some(session.riddle.clone());
+        self.sessions.retain(|other| other.riddle != session.riddle);
+        self.sessions.push(session);
+    }
+
+    /// Replaces the session of the same riddle, if it is still running.
+    pub(crate) fn update(&mut self, session: ChatState) {
+        if let Some(old) = self
+            .sessions
+            .iter_mut()
+            .find(|old| old.riddle == session.riddle)
+        {
+            *old = session;
+        }
+    }
+
+    /// Whether answers go to one of the sessions: one is focused and it is
+    /// not paused.
+    pub(crate) fn takes_answers(&self) -> bool {
+        self.focused().is_some_and(|session| !session.is_paused())
+    }
+
+    /// Stops sending answers to any of the sessions, keeping their progress.
+    pub(crate) fn unfocus(&mut self) {
+        self.focused = None;
+    }
+
+    pub(crate) fn focus(&mut self, code: &str) -> bool {
+        let found = self.get(code).is_some();
+        if found {
+            self.focused = Some(code.to_owned());
+        }
+        found
+    }
+
+    /// Ends the session of the riddle. If it was focused, the most recently
+    /// started of the other sessions gets the focus and is returned.
+    pub(crate) fn end(&mut self, code: &str) -> Option<&ChatState> {
+        self.sessions.retain(|session| session.riddle != code);
+        if self.is_focused(code) {
+            self.refocus()
+        } else {
+            None
+        }
+    }
+
+    /// Pauses the session of the riddle, answers don't go to it until it is
+    /// resumed. Moves the focus like `end`.
+    pub(crate) fn pause(&mut self, code: &str, now: DateTime<Utc>) -> Option<&ChatState> {
+        let session = self
+            .sessions
+            .iter_mut()
+            .find(|session| session.riddle == code)?;
+        session.paused_at.get_or_insert(now);
+        session.last_active = now;
+        if self.is_focused(code) {
+            self.refocus()
+        } else {
+            None
+        }
+    }
+
+    /// Continues a paused session (or just focuses a running one).
+    pub(crate) fn resume(&mut self, code: &str, now: DateTime<Utc>) -> bool {
+        let Some(session) = self
+            .sessions
+            .iter_mut()
+            .find(|session| session.riddle == code)
+        else {
+            return false;
+        };
+        if let Some(paused_at) = session.paused_at.take() {
+            session.paused_for = session.paused_for + (now - paused_at);
+        }
+        session.last_active = now;
+        self.focused = Some(code.to_owned());
+        true
+    }
+
+    /// Removes the sessions that were not active since `idle_since`.
+    pub(crate) fn expire(&mut self, idle_since: DateTime<Utc>) -> Vec<ChatState> {
+        let (expired, active) = std::mem::take(&mut self.sessions)
+            .into_iter()
+            .partition(|session| session.last_active < idle_since);
+        self.sessions = active;
+        if self.focused().is_none() {
+            self.refocus();
+        }
+        expired
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+}
+
+/// An immutable snapshot of a riddle. Every change to a riddle adds a new
+/// version instead of modifying the current one.
+pub(crate) struct RiddleVersion {
     pub name: String,
     pub description: String,
-    pub state_machine: state_machine::StateMachine,
+    pub state_machine: Arc<state_machine::StateMachine>,
+    pub author: UserId,
+    pub created_at: DateTime<Utc>,
+}
+
+impl RiddleVersion {
+    pub(crate) fn new(
+        name: String,
+        description: String,
+        state_machine: state_machine::StateMachine,
+        author: UserId,
+    ) -> Self {
+        Self {
+            name,
+            description,
+            state_machine: Arc::new(state_machine),
+            author,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// A copy of this version made by `author`, e.g. for a rollback.
+    pub(crate) fn copy(&self, author: UserId) -> Self {
+        Self {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            state_machine: self.state_machine.clone(),
+            author,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub(crate) fn to_raw(&self) -> models_raw::Riddle {
+        models_raw::Riddle {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            state_machine: self.state_machine.to_raw(),
+        }
+    }
+}
+
+/// Counted over all versions of a riddle, playtests are not counted.
+#[derive(Default)]
+pub(crate) struct RiddleStats {
+    // wrong answers given in each state
+    pub wrong_answers: HashMap<String, u64>,
+    // how many times players ran out of attempts in each state
+    pub out_of_attempts: HashMap<String, u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    pub(crate) fn parse(text: &str) -> Option<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "easy" => Some(Difficulty::Easy),
+            "medium" => Some(Difficulty::Medium),
+            "hard" => Some(Difficulty::Hard),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difficulty::Easy => write!(f, "easy"),
+            Difficulty::Medium => write!(f, "medium"),
+            Difficulty::Hard => write!(f, "hard"),
+        }
+    }
+}
+
+/// How a riddle is presented in the public catalog, see `/browse`.
+#[derive(Clone, Debug)]
+pub(crate) struct Listing {
+    pub tags: Vec<String>,
+    pub difficulty: Difficulty,
+    pub blurb: String,
+}
+
+pub(crate) struct Riddle {
     pub creator: UserId,
+    // drafts can only be started by their creator and admins
+    pub published: bool,
+    // published riddles with a listing are shown in the catalog
+    pub listing: Option<Listing>,
+    pub stats: RiddleStats,
+    // playtests are not recorded
+    pub solves: Vec<leaderboard::Solve>,
+    // never empty, the last one is the current version
+    versions: Vec<RiddleVersion>,
+}
+
+impl Riddle {
+    pub(crate) fn new(first_version: RiddleVersion) -> Self {
+        Self {
+            creator: first_version.author,
+            published: false,
+            listing: None,
+            stats: RiddleStats::default(),
+            solves: vec![],
+            versions: vec![first_version],
+        }
+    }
+
+    pub(crate) fn current(&self) -> &RiddleVersion {
+        self.versions.last().unwrap()
+    }
+
+    /// Number of the current version, versions are numbered from 1.
+    pub(crate) fn current_number(&self) -> usize {
+        self.versions.len()
+    }
+
+    pub(crate) fn version(&self, number: usize) -> Option<&RiddleVersion> {
+        number
+            .checked_sub(1)
+            .and_then(|index| self.versions.get(index))
+    }
+
+    pub(crate) fn versions(&self) -> impl Iterator<Item = (usize, &RiddleVersion)> {
+        self.versions
+            .iter()
+            .enumerate()
+            .map(|(index, version)| (index + 1, version))
+    }
+
+    pub(crate) fn push_version(&mut self, version: RiddleVersion) -> usize {
+        self.versions.push(version);
+        self.current_number()
+    }
+}
+
+/// Whose data it is: a whole chat, or a member of a group chat who plays on
+/// their own there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct DataKey {
+    pub chat: ChatId,
+    pub user: Option<UserId>,
 }
 
-pub(crate) type ChatData<D> = Arc<Mutex<HashMap<ChatId, D>>>;
+impl DataKey {
+    pub(crate) fn chat(chat: ChatId) -> Self {
+        Self { chat, user: None }
+    }
+
+    /// The sender of `msg` in group chats, the chat itself in private chats
+    /// and for messages without a sender.
+    pub(crate) fn member(msg: &Message) -> Self {
+        Self {
+            chat: msg.chat.id,
+            user: msg
+                .from()
+                .filter(|_| !msg.chat.is_private())
+                .map(|user| user.id),
+        }
+    }
+}
+
+impl fmt::Display for DataKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.user {
+            Some(user) => write!(f, "user {} in chat {}", user, self.chat),
+            None => write!(f, "chat {}", self.chat),
+        }
+    }
+}
+
+pub(crate) type ChatData<D> = Arc<Mutex<HashMap<DataKey, D>>>;
 pub(crate) type Riddles = Arc<Mutex<HashMap<String, Riddle>>>;
+// running races by riddle code, one at a time for every riddle
+pub(crate) type Races = Arc<Mutex<HashMap<String, race::Race>>>;
 
 pub(crate) fn dependencies() -> DependencyMap {
-    dptree::deps![Riddles::default()]
+    dptree::deps![Riddles::default(), Races::default()]
 }
 
+/// The data of the sender of `msg`, see `DataKey::member`.
 pub(crate) async fn get_data<D: Clone + Default>(data: ChatData<D>, msg: Message) -> D {
-    data.lock()
-        .await
-        .get(&msg.chat.id)
-        .cloned()
-        .unwrap_or_default()
+    get_data_at(&data, DataKey::member(&msg)).await
+}
+
+pub(crate) async fn get_data_at<D: Clone + Default>(data: &ChatData<D>, key: DataKey) -> D {
+    data.lock().await.get(&key).cloned().unwrap_or_default()
 }
 
 pub(crate) fn update_data_func<D: Clone + Send + Sync + std::fmt::Debug + 'static>(
@@ -41,9 +531,352 @@ pub(crate) fn update_data_func<D: Clone + Send + Sync + std::fmt::Debug + 'stati
     }
 }
 
-pub(crate) async fn update_data<D: std::fmt::Debug>(new_data: D, msg: Message, data_mut: ChatData<D>) {
-    let mut data = data_mut.lock().await;
-    // let prev_data = data.get(&msg.chat.id);
-    // println!("chat_id: {}, prev_data: {:?}, new_data: {:?}", msg.chat.id, prev_data, new_data);
-    data.insert(msg.chat.id, new_data);
+pub(crate) async fn update_data<D: std::fmt::Debug>(
+    new_data: D,
+    msg: Message,
+    data_mut: ChatData<D>,
+) {
+    update_data_at(new_data, DataKey::member(&msg), &data_mut).await;
+}
+
+pub(crate) async fn update_data_at<D: std::fmt::Debug>(
+    new_data: D,
+    key: DataKey,
+    data_mut: &ChatData<D>,
+) {
+    data_mut.lock().await.insert(key, new_data);
+}
+
+/// Where the answers of `member` go: to their own sessions while one of them
+/// takes answers, to the chat's otherwise.
+fn route(sessions: &HashMap<DataKey, Sessions>, member: DataKey) -> DataKey {
+    if sessions.get(&member).is_some_and(Sessions::takes_answers) {
+        member
+    } else {
+        DataKey::chat(member.chat)
+    }
+}
+
+/// Where the sessions `msg` is for are kept: a group member who plays a
+/// riddle of their own has their answers go to it, everyone else plays the
+/// chat's.
+pub(crate) async fn sessions_key(data: &ChatData<Sessions>, msg: &Message) -> DataKey {
+    route(&*data.lock().await, DataKey::member(msg))
+}
+
+/// Where the session of the riddle `code` of the sender of `msg` is kept,
+/// paused or not: their own if they play the riddle on their own, the
+/// chat's otherwise.
+pub(crate) async fn sessions_key_for(
+    data: &ChatData<Sessions>,
+    msg: &Message,
+    code: &str,
+) -> DataKey {
+    let member = DataKey::member(msg);
+    let data = data.lock().await;
+    if data
+        .get(&member)
+        .is_some_and(|sessions| sessions.get(code).is_some())
+    {
+        member
+    } else {
+        DataKey::chat(msg.chat.id)
+    }
+}
+
+/// Sends the answers of `member` to the sessions at `key`. A group member who
+/// turns to the chat's sessions stops answering in their own.
+pub(crate) async fn route_to(data: &ChatData<Sessions>, member: DataKey, key: DataKey) {
+    if member == key {
+        return;
+    }
+    if let Some(sessions) = data.lock().await.get_mut(&member) {
+        sessions.unfocus();
+    }
+}
+
+/// Stores the sessions at `key`, dropping them once no riddle is left so
+/// that a group member's answers go to the chat's sessions again.
+pub(crate) async fn update_sessions(sessions: Sessions, key: DataKey, data: &ChatData<Sessions>) {
+    let mut data = data.lock().await;
+    if sessions.is_empty() {
+        data.remove(&key);
+    } else {
+        data.insert(key, sessions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(code: &str) -> ChatState {
+        ChatState::new(code.to_owned(), 1, "a".to_owned(), false, false)
+    }
+
+    fn focused(sessions: &Sessions) -> Option<&str> {
+        sessions.focused().map(|session| session.riddle.as_str())
+    }
+
+    #[test]
+    fn sessions() {
+        let mut sessions = Sessions::default();
+        assert!(sessions.is_empty());
+        assert_eq!(focused(&sessions), None);
+
+        sessions.start(session("x"));
+        sessions.start(session("y"));
+        assert_eq!(focused(&sessions), Some("y"));
+
+        assert!(sessions.focus("x"));
+        assert!(!sessions.focus("z"));
+        assert_eq!(focused(&sessions), Some("x"));
+
+        // starting a riddle again replaces its session
+        let mut moved = session("x");
+        moved.state = "b".to_owned();
+        sessions.start(moved);
+        assert_eq!(sessions.iter().count(), 2);
+        assert_eq!(sessions.get("x").unwrap().state, "b");
+
+        // updates only touch running sessions
+        let mut updated = session("y");
+        updated.state = "c".to_owned();
+        sessions.update(updated);
+        sessions.update(session("z"));
+        assert_eq!(sessions.get("y").unwrap().state, "c");
+        assert!(sessions.get("z").is_none());
+
+        // ending the focused session focuses the most recently started one
+        assert_eq!(
+            sessions.end("x").map(|session| session.riddle.as_str()),
+            Some("y")
+        );
+        assert_eq!(focused(&sessions), Some("y"));
+        assert!(sessions.end("x").is_none());
+        assert!(sessions.end("y").is_none());
+        assert!(sessions.is_empty());
+        assert_eq!(focused(&sessions), None);
+    }
+
+    #[test]
+    fn restart() {
+        let mut chat_state = session("x");
+        chat_state.move_to("b".to_owned());
+        chat_state.history.push(step("i", "a", "b", false));
+        chat_state.attempts.insert("b".to_owned(), 2);
+        chat_state.wrong_answers = 3;
+        chat_state.hint_penalty = 5;
+        chat_state.locked_until = Some(Utc::now() + Duration::minutes(5));
+
+        let restarted = chat_state.restart("a".to_owned());
+        assert_eq!(restarted.state, "a");
+        assert!(restarted.history.is_empty());
+        assert_eq!(restarted.attempts, chat_state.attempts);
+        assert_eq!(restarted.wrong_answers, 3);
+        assert_eq!(restarted.hint_penalty, 5);
+        assert_eq!(restarted.locked_until, chat_state.locked_until);
+        assert_eq!(restarted.started_at, chat_state.started_at);
+    }
+
+    fn step(input: &str, from: &str, to: &str, out_of_attempts: bool) -> Move {
+        Move {
+            input: input.to_owned(),
+            from: from.to_owned(),
+            to: to.to_owned(),
+            user: None,
+            out_of_attempts,
+        }
+    }
+
+    fn state_machine(source: &str) -> StateMachine {
+        StateMachine::new(riddle_bot::dsl::parse(source).unwrap())
+    }
+
+    #[test]
+    fn undo() {
+        let state_machine = state_machine(
+            "initial a\na --\"x\"--> b\nb --\"y\"--> c\nc --\"z\"--> d\nundo 2\ncheckpoint d",
+        );
+        let mut chat_state = session("x");
+        assert_eq!(
+            chat_state.undo(&state_machine),
+            Err(UndoError::NothingToUndo)
+        );
+
+        chat_state.history = vec![
+            step("x", "a", "b", false),
+            step("q", "b", "b", false),
+            step("y", "b", "c", false),
+            // wrong answers after the last step are undone with it
+            step("q", "c", "c", false),
+            step("q", "c", "c", false),
+        ];
+        chat_state.move_to("c".to_owned());
+        assert_eq!(chat_state.undo(&state_machine), Ok(()));
+        assert_eq!(chat_state.state, "b");
+        assert_eq!(chat_state.history.len(), 2);
+        assert_eq!(chat_state.undo(&state_machine), Ok(()));
+        assert_eq!(chat_state.state, "a");
+        assert!(chat_state.history.is_empty());
+        assert_eq!(chat_state.undo(&state_machine), Err(UndoError::NoUndosLeft));
+
+        let mut chat_state = session("x");
+        chat_state.history = vec![step("z", "c", "d", false)];
+        chat_state.move_to("d".to_owned());
+        assert_eq!(chat_state.undo(&state_machine), Err(UndoError::Checkpoint));
+
+        assert_eq!(
+            session("x").undo(&self::state_machine("initial a")),
+            Err(UndoError::NotAllowed)
+        );
+    }
+
+    #[test]
+    fn undo_stops_at_failure() {
+        let state_machine =
+            state_machine("initial a\na --\"x\"--> b\nb --\"y\"--> c\nundo\nattempts b 1 fail a");
+        let mut chat_state = session("x");
+        chat_state.history = vec![
+            step("x", "a", "b", false),
+            step("q", "b", "a", true),
+            step("x", "a", "b", false),
+        ];
+        chat_state.move_to("b".to_owned());
+
+        assert_eq!(chat_state.undo(&state_machine), Ok(()));
+        assert_eq!(chat_state.state, "a");
+        assert_eq!(
+            chat_state.undo(&state_machine),
+            Err(UndoError::OutOfAttempts)
+        );
+        assert_eq!(chat_state.state, "a");
+        assert_eq!(chat_state.history.len(), 2);
+    }
+
+    #[test]
+    fn transcript() {
+        let mut chat_state = session("x");
+        chat_state.history = vec![step("x", "a", "b", false), step("q", "b", "b", false)];
+        chat_state.move_to("b".to_owned());
+
+        let transcript = chat_state.transcript();
+        let inputs: Vec<&str> = transcript
+            .steps
+            .iter()
+            .map(|step| step.input.as_str())
+            .collect();
+        assert_eq!(inputs, vec!["x", "q"]);
+        assert!(transcript.steps.iter().all(|step| step.messages.is_none()));
+        assert_eq!(transcript.final_state, "b");
+        assert!(!transcript.accepts);
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let start = Utc::now();
+        let mut sessions = Sessions::default();
+        sessions.start(session("x"));
+        sessions.start(session("y"));
+
+        // pausing the focused session focuses the most recent running one
+        assert_eq!(
+            sessions
+                .pause("y", start)
+                .map(|session| session.riddle.as_str()),
+            Some("x")
+        );
+        assert!(sessions.get("y").unwrap().is_paused());
+        assert!(sessions.pause("x", start).is_none());
+        assert_eq!(focused(&sessions), None);
+        assert!(sessions.pause("z", start).is_none());
+
+        // paused time is not time spent on the riddle
+        let later = start + Duration::minutes(10);
+        let spent = sessions.get("y").unwrap().time_spent(later);
+        assert!(sessions.resume("y", later));
+        assert!(!sessions.resume("z", later));
+        assert_eq!(focused(&sessions), Some("y"));
+        let y = sessions.get("y").unwrap();
+        assert!(!y.is_paused());
+        assert_eq!(y.paused_for, Duration::minutes(10));
+        assert_eq!(y.time_spent(later), spent);
+    }
+
+    #[test]
+    fn expire() {
+        let now = Utc::now();
+        let mut sessions = Sessions::default();
+        let mut old = session("x");
+        old.last_active = now - Duration::hours(2);
+        sessions.start(old);
+        sessions.start(session("y"));
+        sessions.pause("y", now);
+
+        // the focused session expired, the remaining one is paused
+        sessions.focus("x");
+        let expired = sessions.expire(now - Duration::hours(1));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].riddle, "x");
+        assert!(sessions.get("y").is_some());
+        assert_eq!(focused(&sessions), None);
+
+        sessions.resume("y", now);
+        assert_eq!(focused(&sessions), Some("y"));
+        sessions.expire(now + Duration::hours(1));
+        assert!(sessions.is_empty());
+    }
+
+    #[test]
+    fn route() {
+        let chat = DataKey::chat(ChatId(-1));
+        let member = DataKey {
+            chat: ChatId(-1),
+            user: Some(UserId(1)),
+        };
+        let mut data = HashMap::new();
+        assert_eq!(super::route(&data, member), chat);
+
+        let mut sessions = Sessions::default();
+        sessions.start(session("x"));
+        data.insert(member, sessions.clone());
+        assert_eq!(super::route(&data, member), member);
+
+        // paused sessions don't take the member's answers
+        sessions.pause("x", Utc::now());
+        data.insert(member, sessions.clone());
+        assert_eq!(super::route(&data, member), chat);
+
+        sessions.resume("x", Utc::now());
+        sessions.unfocus();
+        data.insert(member, sessions);
+        assert_eq!(super::route(&data, member), chat);
+    }
+
+    #[tokio::test]
+    async fn route_to_team() {
+        let chat = DataKey::chat(ChatId(-1));
+        let member = DataKey {
+            chat: ChatId(-1),
+            user: Some(UserId(1)),
+        };
+        let other = DataKey {
+            chat: ChatId(-1),
+            user: Some(UserId(2)),
+        };
+        let data = ChatData::<Sessions>::default();
+        let mut sessions = Sessions::default();
+        sessions.start(session("x"));
+        data.lock().await.insert(member, sessions);
+
+        // a team riddle started by someone else, e.g. the bot's message with
+        // the catalog's buttons, leaves the member's own riddle alone
+        route_to(&data, other, chat).await;
+        assert_eq!(super::route(&*data.lock().await, member), member);
+
+        // the member who pressed the button turns to the team
+        route_to(&data, member, chat).await;
+        assert_eq!(super::route(&*data.lock().await, member), chat);
+        assert!(data.lock().await[&member].get("x").is_some());
+    }
 }
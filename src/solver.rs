@@ -0,0 +1,305 @@
+//! Finds the shortest way to solve a riddle: a breadth-first search from the
+//! initial state to the closest accepting state.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use regex_syntax::hir::{Class, Hir, HirKind};
+
+use crate::{
+    models::{Edge, Prompt},
+    models_raw::{Playthrough, Step},
+    state_machine::StateMachine,
+};
+
+// how many example inputs are generated for a single regex
+const MAX_EXAMPLES: usize = 16;
+
+// inputs tried for `*` prompts
+const ANY_INPUTS: [&str; 4] = ["anything", "something else", "42", "?"];
+
+/// Why a riddle can't be solved.
+#[derive(Debug, PartialEq)]
+pub struct Unsolvable {
+    pub has_accepting_states: bool,
+    pub reachable_states: Vec<String>,
+    // edges leading out of the reachable states that no input was found for
+    pub unused_edges: Vec<String>,
+}
+
+impl fmt::Display for Unsolvable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.has_accepting_states {
+            return write!(f, "the state machine has no accepting states");
+        }
+        write!(
+            f,
+            "no accepting state can be reached, the reachable states are: {}",
+            self.reachable_states.join(", ")
+        )?;
+        if !self.unused_edges.is_empty() {
+            write!(
+                f,
+                "\nno input was found that fires these edges: {}",
+                self.unused_edges.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn class_examples(ranges: impl Iterator<Item = (char, char)>) -> Vec<String> {
+    let ranges: Vec<(char, char)> = ranges.collect();
+    let contains = |c: char| {
+        ranges
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&c))
+    };
+
+    let mut bounds: Vec<char> = ranges
+        .iter()
+        .flat_map(|(start, end)| [*start, *end])
+        .filter(|c| !c.is_control())
+        .collect();
+    // readable characters first
+    bounds.sort_by_key(|c| (!c.is_lowercase(), !c.is_ascii_alphanumeric()));
+
+    let mut chars: Vec<char> = vec![];
+    for c in "ax0_ -".chars().filter(|c| contains(*c)).chain(bounds) {
+        if !chars.contains(&c) {
+            chars.push(c);
+        }
+    }
+    chars.into_iter().take(3).map(String::from).collect()
+}
+
+/// Strings matched by the regex, as far as they are easy to come up with.
+/// Lookarounds such as `^` or `\b` are ignored, callers have to check the
+/// examples against the regex.
+fn examples(hir: &Hir) -> Vec<String> {
+    let mut examples: Vec<String> = match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => vec![String::new()],
+        HirKind::Literal(literal) => vec![String::from_utf8_lossy(&literal.0).into_owned()],
+        HirKind::Class(Class::Unicode(class)) => class_examples(
+            class
+                .ranges()
+                .iter()
+                .map(|range| (range.start(), range.end())),
+        ),
+        HirKind::Class(Class::Bytes(class)) => class_examples(
+            class
+                .ranges()
+                .iter()
+                .map(|range| (char::from(range.start()), char::from(range.end()))),
+        ),
+        HirKind::Capture(capture) => examples(&capture.sub),
+        HirKind::Repetition(repetition) => {
+            let sub = examples(&repetition.sub);
+            let mut counts = vec![repetition.min];
+            if repetition.max.is_none_or(|max| max > repetition.min) {
+                counts.push(repetition.min + 1);
+            }
+            counts
+                .into_iter()
+                .flat_map(|count| {
+                    sub.iter()
+                        .map(move |example| example.repeat(count as usize))
+                })
+                .collect()
+        }
+        HirKind::Concat(hirs) => hirs.iter().fold(vec![String::new()], |prefixes, hir| {
+            let suffixes = examples(hir);
+            prefixes
+                .iter()
+                .flat_map(|prefix| suffixes.iter().map(move |suffix| prefix.clone() + suffix))
+                .take(MAX_EXAMPLES)
+                .collect()
+        }),
+        HirKind::Alternation(hirs) => hirs.iter().flat_map(examples).collect(),
+    };
+
+    let mut seen = HashSet::new();
+    examples.retain(|example| seen.insert(example.clone()));
+    examples.truncate(MAX_EXAMPLES);
+    examples
+}
+
+/// An input that fires the edge at `index`: it matches its prompt and none
+/// of the edges before it, since those are tried first.
+fn input_for(edges: &[Edge], index: usize) -> Option<String> {
+    let candidates = match &edges[index].prompt {
+        Prompt::Text(text) => vec![text.clone()],
+        Prompt::Regex(regex) => regex_syntax::parse(regex.as_str())
+            .map(|hir| examples(&hir))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|example| regex.is_match(example))
+            .collect(),
+        Prompt::Either => ANY_INPUTS.iter().map(|input| input.to_string()).collect(),
+    };
+
+    // empty messages can't be sent
+    candidates.into_iter().find(|input| {
+        !input.trim().is_empty() && !edges[..index].iter().any(|edge| edge.prompt.matches(input))
+    })
+}
+
+/// The shortest sequence of inputs that solves the riddle, as a playthrough
+/// that can be added to the riddle's tests.
+pub fn shortest_solution(state_machine: &StateMachine) -> Result<Playthrough, Unsolvable> {
+    let initial_state = state_machine.initial_state.clone();
+
+    // for every state found, the state it was reached from and the input
+    let mut previous: HashMap<String, (String, String)> = HashMap::new();
+    let mut reachable_states = vec![initial_state.clone()];
+    let mut unused_edges = vec![];
+    let mut queue = VecDeque::new();
+
+    let mut final_state = None;
+    if state_machine.is_accepting(&initial_state) {
+        final_state = Some(initial_state.clone());
+    } else {
+        queue.push_back(initial_state.clone());
+    }
+
+    'search: while let Some(name) = queue.pop_front() {
+        let Some(state) = state_machine.state(&name) else {
+            continue;
+        };
+
+        for (index, edge) in state.edges.iter().enumerate() {
+            let Some(next) = edge.next.as_ref() else {
+                continue;
+            };
+            if reachable_states.contains(next) {
+                continue;
+            }
+            let Some(input) = input_for(&state.edges, index) else {
+                unused_edges.push((edge.to_raw().describe(&name), next.clone()));
+                continue;
+            };
+
+            reachable_states.push(next.clone());
+            previous.insert(next.clone(), (name.clone(), input));
+            if state_machine.is_accepting(next) {
+                final_state = Some(next.clone());
+                break 'search;
+            }
+            queue.push_back(next.clone());
+        }
+    }
+
+    let Some(final_state) = final_state else {
+        return Err(Unsolvable {
+            has_accepting_states: !state_machine.to_raw().accepting_states.is_empty(),
+            unused_edges: unused_edges
+                .into_iter()
+                .filter(|(_, next)| !reachable_states.contains(next))
+                .map(|(edge, _)| edge)
+                .collect(),
+            reachable_states,
+        });
+    };
+
+    let mut steps = vec![];
+    let mut state = &final_state;
+    while let Some((from, input)) = previous.get(state) {
+        steps.push(Step {
+            input: input.clone(),
+            messages: None,
+        });
+        state = from;
+    }
+    steps.reverse();
+
+    Ok(Playthrough {
+        name: "shortest solution".to_owned(),
+        steps,
+        final_state,
+        accepts: true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl;
+
+    fn solve(source: &str) -> Result<Playthrough, Unsolvable> {
+        shortest_solution(&StateMachine::new(dsl::parse(source).unwrap()))
+    }
+
+    fn inputs(playthrough: &Playthrough) -> Vec<&str> {
+        playthrough
+            .steps
+            .iter()
+            .map(|step| step.input.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn shortest_path() {
+        let solution = solve(
+            r#"
+                initial a
+                accept end
+                a --"long"--> b
+                a --"^[0-9]{3}$"/regex--> c
+                b --"x"--> c
+                c --"(?i)^(open|unlock) door$"/regex--> end
+                c --*--> a
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(inputs(&solution), vec!["000", "open door"]);
+        assert_eq!(solution.final_state, "end");
+        assert_eq!(
+            solution.to_string(),
+            "test \"shortest solution\"\n> \"000\"\n> \"open door\"\nexpect accept end"
+        );
+    }
+
+    #[test]
+    fn shadowed_edges() {
+        // the first example of the regex is taken by the earlier edge
+        let solution = solve(
+            r#"
+                initial a
+                accept end
+                a --"a"--> .
+                a --"a|b"/regex--> b
+                b --"anything"--> .
+                b --*--> end
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(inputs(&solution), vec!["b", "something else"]);
+    }
+
+    #[test]
+    fn unsolvable() {
+        assert_eq!(
+            solve("initial a\naccept end\na --\"x\"--> b\na --\"y\"--> .\nb --*--> a").unwrap_err(),
+            Unsolvable {
+                has_accepting_states: true,
+                reachable_states: vec!["a".to_owned(), "b".to_owned()],
+                unused_edges: vec![],
+            }
+        );
+        assert_eq!(
+            solve("initial a\naccept end\na --\"x\"--> a\na --\"x\"--> end")
+                .unwrap_err()
+                .to_string(),
+            "no accepting state can be reached, the reachable states are: a\n\
+             no input was found that fires these edges: a --\"x\"--> end"
+        );
+        assert!(
+            !solve("initial a\na --*--> a")
+                .unwrap_err()
+                .has_accepting_states
+        );
+        assert!(solve("initial a\naccept a").unwrap().steps.is_empty());
+    }
+}
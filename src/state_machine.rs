@@ -1,14 +1,83 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::fmt;
 
 use crate::models;
 use crate::models_raw;
 use crate::utils::HandlerResult;
 
-pub(crate) struct StateMachine {
+pub struct StateMachine {
     pub initial_state: String,
     accepting_states: HashSet<String>,
     states: HashMap<String, models::State>,
+    // names of the states in the order they were defined
+    state_order: Vec<String>,
+    tests: Vec<models_raw::Playthrough>,
+    rate_limit: Option<models_raw::RateLimit>,
+    undo: Option<models_raw::Undo>,
+    leaderboard: models_raw::LeaderboardVisibility,
+    play_mode: models_raw::PlayMode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EdgeOutcome {
+    // the prompt was checked and didn't match the input
+    NotMatched,
+    Matched,
+    // an earlier edge matched, so the prompt wasn't checked
+    Skipped,
+}
+
+/// What happened during a single [`StateMachine::apply`], for debugging
+/// riddles.
+#[derive(Debug, PartialEq)]
+pub struct Trace {
+    pub from: String,
+    pub edges: Vec<(String, EdgeOutcome)>,
+    pub actions: Vec<String>,
+    pub to: String,
+    pub accepting: bool,
+    // the input is a wrong answer, see [`StateMachine::apply_edge`]
+    pub wrong: bool,
+}
+
+/// The result of a player's answer once attempts are counted, see
+/// [`StateMachine::count_attempt`].
+#[derive(Debug, PartialEq)]
+pub struct Answer {
+    pub state: String,
+    // no edge of the state matched the answer
+    pub wrong: bool,
+    // the player ran out of attempts with this answer
+    pub out_of_attempts: bool,
+    // seconds the player can't answer for, 0 if they can go on
+    pub lockout: u32,
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "State: {}", self.from)?;
+        for (edge, outcome) in &self.edges {
+            let marker = match outcome {
+                EdgeOutcome::NotMatched => "no match",
+                EdgeOutcome::Matched => "MATCHED",
+                EdgeOutcome::Skipped => "skipped",
+            };
+            writeln!(f, "  [{}] {}", marker, edge)?;
+        }
+        if self.edges.is_empty() {
+            writeln!(f, "  (no edges)")?;
+        }
+        if !self.actions.is_empty() {
+            writeln!(f, "Actions: {}", self.actions.join(", "))?;
+        }
+        write!(
+            f,
+            "Now in: {}{}",
+            self.to,
+            if self.accepting { " (accepting)" } else { "" }
+        )
+    }
 }
 
 // #[derive(thiserror::Error)]
@@ -17,47 +86,381 @@ pub(crate) struct StateMachine {
 // }
 
 impl StateMachine {
-    pub(crate) fn new(state_machine_raw: models_raw::StateMachine) -> Self {
+    pub fn new(state_machine_raw: models_raw::StateMachine) -> Self {
         let mut states = HashMap::new();
+        let mut state_order = vec![];
         for state in state_machine_raw.states {
-            states.insert(state.name.clone(), models::State::new(state));
+            let name = state.name.clone();
+            if states
+                .insert(name.clone(), models::State::new(state))
+                .is_none()
+            {
+                state_order.push(name);
+            }
         }
 
         Self {
             initial_state: state_machine_raw.initial_state,
             accepting_states: state_machine_raw.accepting_states.into_iter().collect(),
             states,
+            state_order,
+            tests: state_machine_raw.tests,
+            rate_limit: state_machine_raw.rate_limit,
+            undo: state_machine_raw.undo,
+            leaderboard: state_machine_raw.leaderboard,
+            play_mode: state_machine_raw.play_mode,
+        }
+    }
+
+    /// The author's limit on answers, if they set one.
+    pub fn rate_limit(&self) -> Option<models_raw::RateLimit> {
+        self.rate_limit
+    }
+
+    /// Whether players may take back answers, and how many times.
+    pub fn undo(&self) -> Option<models_raw::Undo> {
+        self.undo
+    }
+
+    pub fn leaderboard(&self) -> models_raw::LeaderboardVisibility {
+        self.leaderboard
+    }
+
+    pub fn play_mode(&self) -> models_raw::PlayMode {
+        self.play_mode
+    }
+
+    /// Example playthroughs that came with the definition, see
+    /// [`crate::playthroughs`].
+    pub fn tests(&self) -> &[models_raw::Playthrough] {
+        &self.tests
+    }
+
+    /// Converts the state machine back to its raw form. States keep the order
+    /// they were defined in, so exporting an imported machine is stable.
+    pub fn to_raw(&self) -> models_raw::StateMachine {
+        let mut accepting_states: Vec<String> = self
+            .state_order
+            .iter()
+            .filter(|name| self.accepting_states.contains(*name))
+            .cloned()
+            .collect();
+        let mut undefined_accepting_states: Vec<String> = self
+            .accepting_states
+            .iter()
+            .filter(|name| !self.states.contains_key(*name))
+            .cloned()
+            .collect();
+        undefined_accepting_states.sort();
+        accepting_states.extend(undefined_accepting_states);
+
+        models_raw::StateMachine {
+            initial_state: self.initial_state.clone(),
+            accepting_states,
+            states: self
+                .state_order
+                .iter()
+                .map(|name| self.states[name].to_raw(name))
+                .collect(),
+            tests: self.tests.clone(),
+            rate_limit: self.rate_limit,
+            undo: self.undo,
+            leaderboard: self.leaderboard,
+            play_mode: self.play_mode,
         }
     }
 
-    pub(crate) async fn apply(
+    pub async fn apply(
         &self,
         applier: &mut impl models::ActionApplier,
         state_name: &str,
         input: &str,
     ) -> HandlerResult<String> {
+        Ok(self
+            .apply_edge(applier, state_name, input)
+            .await?
+            .unwrap_or_else(|| state_name.to_string()))
+    }
+
+    /// Like [`StateMachine::apply`], but returns `None` if the input is a
+    /// wrong answer: no edge matched it, or a `*` edge that stays in the
+    /// state did (the usual "Nope" feedback).
+    pub async fn apply_edge(
+        &self,
+        applier: &mut impl models::ActionApplier,
+        state_name: &str,
+        input: &str,
+    ) -> HandlerResult<Option<String>> {
         let state = self.states.get(state_name).unwrap();
         let edge_opt = state.edges.iter().find(|edge| edge.prompt.matches(input));
 
         match edge_opt {
-            None => Ok(state_name.to_string()),
+            None => Ok(None),
             Some(edge) => {
                 for action in &edge.actions {
                     action.apply(applier).await?;
                 }
 
-                if let Some(next) = edge.next.as_ref() {
-                    Ok(next.clone())
+                if edge.is_catch_all_loop(state_name) {
+                    Ok(None)
+                } else if let Some(next) = edge.next.as_ref() {
+                    Ok(Some(next.clone()))
                 } else {
-                    Ok(state_name.to_string())
+                    Ok(Some(state_name.to_string()))
                 }
             }
         }
     }
 
-    pub(crate) fn is_accepting(&self, state_name: &str) -> bool {
+    /// Like [`StateMachine::apply`], but also reports which edges were
+    /// considered and which actions were fired.
+    pub async fn trace(
+        &self,
+        applier: &mut impl models::ActionApplier,
+        state_name: &str,
+        input: &str,
+    ) -> HandlerResult<Trace> {
+        let state = self.states.get(state_name).unwrap();
+        let matched = state
+            .edges
+            .iter()
+            .position(|edge| edge.prompt.matches(input));
+
+        let edges = state
+            .edges
+            .iter()
+            .enumerate()
+            .map(|(index, edge)| {
+                let outcome = match matched {
+                    Some(matched) if index == matched => EdgeOutcome::Matched,
+                    Some(matched) if index > matched => EdgeOutcome::Skipped,
+                    _ => EdgeOutcome::NotMatched,
+                };
+                (edge.to_raw().describe(state_name), outcome)
+            })
+            .collect();
+        let actions = matched
+            .map(|matched| {
+                state.edges[matched]
+                    .actions
+                    .iter()
+                    .map(|action| action.to_raw().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let wrong =
+            matched.is_none_or(|matched| state.edges[matched].is_catch_all_loop(state_name));
+        let to = self.apply(applier, state_name, input).await?;
+
+        Ok(Trace {
+            from: state_name.to_owned(),
+            edges,
+            actions,
+            accepting: self.is_accepting(&to),
+            to,
+            wrong,
+        })
+    }
+
+    /// Like [`StateMachine::apply`], but also counts wrong answers in
+    /// `attempts`, see [`StateMachine::count_attempt`].
+    pub async fn answer(
+        &self,
+        applier: &mut impl models::ActionApplier,
+        state_name: &str,
+        input: &str,
+        attempts: &mut HashMap<String, u32>,
+    ) -> HandlerResult<Answer> {
+        let to = self.apply_edge(applier, state_name, input).await?;
+        Ok(self.count_attempt(state_name, to, attempts))
+    }
+
+    /// Counts the answer given in `from`, `to` is the state the matched edge
+    /// led to or `None` for a wrong answer, see [`StateMachine::apply_edge`].
+    /// Edges other than `*` that stay in the state (e.g. "warmer" feedback)
+    /// are not wrong answers. `attempts` holds the player's wrong answers per
+    /// state. Once a player runs out of attempts the count starts over and
+    /// they are moved to the state's failure state, if it has one.
+    pub fn count_attempt(
+        &self,
+        from: &str,
+        to: Option<String>,
+        attempts: &mut HashMap<String, u32>,
+    ) -> Answer {
+        let mut answer = Answer {
+            wrong: to.is_none(),
+            state: to.unwrap_or_else(|| from.to_owned()),
+            out_of_attempts: false,
+            lockout: 0,
+        };
+        if !answer.wrong {
+            return answer;
+        }
+
+        let count = attempts.entry(from.to_owned()).or_default();
+        *count += 1;
+
+        let Some(state) = self.states.get(from) else {
+            return answer;
+        };
+        if state
+            .max_attempts
+            .is_some_and(|max_attempts| *count >= max_attempts)
+        {
+            attempts.remove(from);
+            answer.out_of_attempts = true;
+            answer.lockout = state.lockout;
+            if let Some(failure_state) = &state.failure_state {
+                answer.state = failure_state.clone();
+            }
+        }
+
+        answer
+    }
+
+    pub fn is_accepting(&self, state_name: &str) -> bool {
         self.accepting_states.contains(state_name)
     }
+
+    /// Differences between this machine and `new`, one change per line.
+    pub fn diff(&self, new: &StateMachine) -> Vec<String> {
+        let old_raw = self.to_raw();
+        let new_raw = new.to_raw();
+        let mut changes = vec![];
+
+        if old_raw.initial_state != new_raw.initial_state {
+            changes.push(format!(
+                "~ initial {} -> {}",
+                old_raw.initial_state, new_raw.initial_state
+            ));
+        }
+        if old_raw.rate_limit != new_raw.rate_limit {
+            changes.push("~ rate_limit".to_owned());
+        }
+        if old_raw.undo != new_raw.undo {
+            changes.push("~ undo".to_owned());
+        }
+        if old_raw.leaderboard != new_raw.leaderboard {
+            changes.push("~ leaderboard".to_owned());
+        }
+        if old_raw.play_mode != new_raw.play_mode {
+            changes.push("~ play_mode".to_owned());
+        }
+        for name in old_raw
+            .accepting_states
+            .iter()
+            .filter(|name| !new.is_accepting(name))
+        {
+            changes.push(format!("- accept {}", name));
+        }
+        for name in new_raw
+            .accepting_states
+            .iter()
+            .filter(|name| !self.is_accepting(name))
+        {
+            changes.push(format!("+ accept {}", name));
+        }
+        for name in self.removed_states(new) {
+            changes.push(format!("- state {}", name));
+        }
+        for name in new.removed_states(self) {
+            changes.push(format!("+ state {}", name));
+        }
+
+        for old_state in &old_raw.states {
+            let Some(new_state) = new_raw
+                .states
+                .iter()
+                .find(|state| state.name == old_state.name)
+            else {
+                continue;
+            };
+            for edge in old_state
+                .edges
+                .iter()
+                .filter(|edge| !new_state.edges.contains(edge))
+            {
+                changes.push(format!("- {}", edge.describe(&old_state.name)));
+            }
+            for edge in new_state
+                .edges
+                .iter()
+                .filter(|edge| !old_state.edges.contains(edge))
+            {
+                changes.push(format!("+ {}", edge.describe(&new_state.name)));
+            }
+            if old_state.description != new_state.description {
+                changes.push(format!("~ description {}", new_state.name));
+            }
+            if old_state.hints != new_state.hints {
+                changes.push(format!("~ hints {}", new_state.name));
+            }
+            if (
+                old_state.max_attempts,
+                &old_state.failure_state,
+                old_state.lockout,
+            ) != (
+                new_state.max_attempts,
+                &new_state.failure_state,
+                new_state.lockout,
+            ) {
+                changes.push(format!("~ attempts {}", new_state.name));
+            }
+            if old_state.checkpoint != new_state.checkpoint {
+                changes.push(format!("~ checkpoint {}", new_state.name));
+            }
+            if old_state.milestone != new_state.milestone {
+                changes.push(format!("~ milestone {}", new_state.name));
+            }
+        }
+
+        changes
+    }
+
+    pub fn has_state(&self, state_name: &str) -> bool {
+        self.states.contains_key(state_name)
+    }
+
+    pub fn state(&self, state_name: &str) -> Option<&models::State> {
+        self.states.get(state_name)
+    }
+
+    pub fn description(&self, state_name: &str) -> Option<&str> {
+        self.state(state_name)
+            .and_then(|state| state.description.as_deref())
+    }
+
+    /// Whether answers that led to the state can't be undone.
+    pub fn is_checkpoint(&self, state_name: &str) -> bool {
+        self.state(state_name).is_some_and(|state| state.checkpoint)
+    }
+
+    /// Whether reaching the state is announced in races.
+    pub fn is_milestone(&self, state_name: &str) -> bool {
+        self.state(state_name).is_some_and(|state| state.milestone)
+    }
+
+    pub fn milestone_count(&self) -> usize {
+        self.states.values().filter(|state| state.milestone).count()
+    }
+
+    /// Hints of the state, in the order they are revealed.
+    pub fn hints(&self, state_name: &str) -> &[models_raw::Hint] {
+        self.state(state_name)
+            .map(|state| state.hints.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// States of this machine that `new` does not define anymore. Players who
+    /// are in one of them cannot continue once the machine is replaced.
+    pub fn removed_states(&self, new: &StateMachine) -> Vec<String> {
+        self.state_order
+            .iter()
+            .filter(|name| !new.has_state(name))
+            .cloned()
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -90,6 +493,145 @@ mod tests {
         }
     }
 
+    #[test]
+    fn raw_round_trip() {
+        let source = r#"
+            initial start
+            accept end, elsewhere
+            start --"a"/regex--> middle : say "1", send 5 "2"
+            middle --*--> elsewhere
+            middle --"b"--> end
+        "#;
+        let raw = crate::dsl::parse(source).unwrap();
+        let exported = StateMachine::new(crate::dsl::parse(source).unwrap()).to_raw();
+        assert_eq!(exported.accepting_states, raw.accepting_states);
+        assert_eq!(exported.states, raw.states);
+
+        let json = serde_json::to_string(&exported).unwrap();
+        let reimported = StateMachine::new(serde_json::from_str(&json).unwrap()).to_raw();
+        assert_eq!(serde_json::to_string(&reimported).unwrap(), json);
+    }
+
+    #[test]
+    fn removed_states() {
+        let old = StateMachine::new(
+            crate::dsl::parse("initial a\na --\"x\"--> b\nb --\"y\"--> c").unwrap(),
+        );
+        let new = StateMachine::new(crate::dsl::parse("initial a\na --\"x\"--> c").unwrap());
+        assert_eq!(old.removed_states(&new), vec!["b".to_string()]);
+        assert!(new.removed_states(&old).is_empty());
+    }
+
+    #[test]
+    fn diff() {
+        let old = StateMachine::new(
+            crate::dsl::parse("initial a\naccept c\na --\"x\"--> b\nb --\"y\"--> c").unwrap(),
+        );
+        let new = StateMachine::new(
+            crate::dsl::parse("initial a\naccept c\na --\"x\"--> c : say \"hi\"").unwrap(),
+        );
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                "- state b".to_string(),
+                "- a --\"x\"--> b".to_string(),
+                "+ a --\"x\"--> c : say \"hi\"".to_string(),
+            ]
+        );
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[tokio::test]
+    async fn trace() {
+        let state_machine = StateMachine::new(
+            crate::dsl::parse(
+                "initial a\naccept b\na --\"x\"--> a\na --*--> b : say \"hi\"\na --\"y\"--> a",
+            )
+            .unwrap(),
+        );
+
+        let mut applier = Applier::new();
+        let trace = state_machine.trace(&mut applier, "a", "z").await.unwrap();
+        assert_eq!(
+            trace,
+            Trace {
+                from: "a".to_string(),
+                edges: vec![
+                    ("a --\"x\"--> a".to_string(), EdgeOutcome::NotMatched),
+                    ("a --*--> b : say \"hi\"".to_string(), EdgeOutcome::Matched),
+                    ("a --\"y\"--> a".to_string(), EdgeOutcome::Skipped),
+                ],
+                actions: vec!["say \"hi\"".to_string()],
+                to: "b".to_string(),
+                accepting: true,
+                wrong: false,
+            }
+        );
+        // an edge that stays in the state on purpose is not a wrong answer
+        let trace = state_machine.trace(&mut applier, "a", "x").await.unwrap();
+        assert!(!trace.wrong);
+        assert_eq!(applier.messages, vec!["hi".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn attempts() {
+        let state_machine = StateMachine::new(
+            crate::dsl::parse(
+                "initial a\naccept c\na --\"x\"--> c\na --\"w\"--> . : say \"warmer\"\na --*--> . : say \"no\"\nattempts a 2 fail b lockout 30",
+            )
+            .unwrap(),
+        );
+        let mut attempts = HashMap::new();
+        let mut applier = Applier::new();
+
+        let answer = state_machine
+            .answer(&mut applier, "a", "y", &mut attempts)
+            .await
+            .unwrap();
+        assert_eq!(
+            answer,
+            Answer {
+                state: "a".to_string(),
+                wrong: true,
+                out_of_attempts: false,
+                lockout: 0,
+            }
+        );
+        assert_eq!(attempts["a"], 1);
+
+        // staying in the state through an edge other than `*` is not a
+        // wrong answer
+        let answer = state_machine
+            .answer(&mut applier, "a", "w", &mut attempts)
+            .await
+            .unwrap();
+        assert!(!answer.wrong);
+        assert_eq!(answer.state, "a");
+        assert_eq!(attempts["a"], 1);
+
+        let answer = state_machine
+            .answer(&mut applier, "a", "y", &mut attempts)
+            .await
+            .unwrap();
+        assert_eq!(
+            answer,
+            Answer {
+                state: "b".to_string(),
+                wrong: true,
+                out_of_attempts: true,
+                lockout: 30,
+            }
+        );
+        assert!(attempts.is_empty());
+
+        let answer = state_machine
+            .answer(&mut applier, "a", "x", &mut attempts)
+            .await
+            .unwrap();
+        assert!(!answer.wrong);
+        assert_eq!(answer.state, "c");
+    }
+
     #[tokio::test]
     async fn simple_test() {
         let state_machine = StateMachine {
@@ -104,6 +646,7 @@ mod tests {
                             next: Some("2".to_string()),
                             actions: vec![models::Action::Message("1-2".to_string())],
                         }],
+                        ..Default::default()
                     },
                 ),
                 (
@@ -121,9 +664,16 @@ mod tests {
                                 actions: vec![models::Action::Message("2-3".to_string())],
                             },
                         ],
+                        ..Default::default()
                     },
                 ),
             ]),
+            state_order: vec!["1".to_string(), "2".to_string()],
+            tests: vec![],
+            rate_limit: None,
+            undo: None,
+            leaderboard: Default::default(),
+            play_mode: Default::default(),
         };
 
         let mut applier = Applier::new();
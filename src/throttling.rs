@@ -0,0 +1,227 @@
+//! Keeps players from brute-forcing answers and riddle codes. Users who keep
+//! going while throttled are blocked for a while and reported to the admins.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use teloxide::prelude::*;
+use tokio::sync::Mutex;
+
+use crate::{
+    admin_commands,
+    models_raw::RateLimit,
+    rate_limit::{parse_limit, TokenBucket},
+    utils::{send_message, HandlerResult},
+};
+
+const DEFAULT_ANSWER_LIMIT: RateLimit = RateLimit {
+    requests: 10,
+    seconds: 60,
+};
+const DEFAULT_CODE_LIMIT: RateLimit = RateLimit {
+    requests: 5,
+    seconds: 60,
+};
+
+// throttled requests in a row after which a user is blocked
+const MAX_STRIKES: u32 = 5;
+const BLOCK_DURATION: Duration = Duration::from_secs(15 * 60);
+// how often buckets that refilled completely are dropped
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, Debug)]
+pub(crate) enum Request {
+    // an answer in the given riddle, with the riddle's own limit if it has one
+    Answer {
+        riddle: String,
+        limit: Option<RateLimit>,
+    },
+    // a riddle code sent to /startriddle or /testriddle
+    Code,
+}
+
+enum Verdict {
+    Allowed,
+    Throttled(Duration),
+    // the user has just been blocked
+    Blocked,
+    // the user was blocked before, requests are ignored silently
+    StillBlocked,
+}
+
+pub(crate) struct Limits {
+    answer_limit: RateLimit,
+    code_limit: RateLimit,
+    answers: HashMap<(UserId, String), TokenBucket>,
+    codes: HashMap<UserId, TokenBucket>,
+    // throttled requests since the user's last allowed one
+    strikes: HashMap<UserId, u32>,
+    blocked_until: HashMap<UserId, Instant>,
+    swept_at: Instant,
+}
+
+pub(crate) type RateLimits = Arc<Mutex<Limits>>;
+
+// read when the bot starts, unlike `admin_commands::admins_from_env`
+fn limit_from_env(name: &str, default: RateLimit) -> RateLimit {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| parse_limit(&value))
+        .unwrap_or(default)
+}
+
+pub(crate) fn dependencies() -> DependencyMap {
+    let limits = Limits {
+        answer_limit: limit_from_env("ANSWER_RATE_LIMIT", DEFAULT_ANSWER_LIMIT),
+        code_limit: limit_from_env("CODE_RATE_LIMIT", DEFAULT_CODE_LIMIT),
+        answers: HashMap::new(),
+        codes: HashMap::new(),
+        strikes: HashMap::new(),
+        blocked_until: HashMap::new(),
+        swept_at: Instant::now(),
+    };
+    dptree::deps![RateLimits::new(Mutex::new(limits))]
+}
+
+impl Limits {
+    /// Forgets the buckets that refilled completely and the blocks that are
+    /// over, so that the limits don't grow with every user and riddle. A
+    /// bucket created again starts full, just like the dropped one.
+    fn sweep(&mut self, now: Instant) {
+        self.answers.retain(|_, bucket| !bucket.is_full(now));
+        self.codes.retain(|_, bucket| !bucket.is_full(now));
+        self.blocked_until.retain(|_, until| now < *until);
+        // strikes only matter while the user is being throttled
+        let answers = &self.answers;
+        let codes = &self.codes;
+        self.strikes.retain(|user_id, _| {
+            codes.contains_key(user_id) || answers.keys().any(|(user, _)| user == user_id)
+        });
+        self.swept_at = now;
+    }
+
+    fn check(&mut self, user_id: UserId, request: &Request, now: Instant) -> Verdict {
+        if now.saturating_duration_since(self.swept_at) >= SWEEP_INTERVAL {
+            self.sweep(now);
+        }
+
+        if let Some(until) = self.blocked_until.get(&user_id) {
+            if now < *until {
+                return Verdict::StillBlocked;
+            }
+            self.blocked_until.remove(&user_id);
+        }
+
+        let taken = match request {
+            Request::Answer { riddle, limit } => {
+                let limit = limit.unwrap_or(self.answer_limit);
+                self.answers
+                    .entry((user_id, riddle.clone()))
+                    .or_insert_with(|| TokenBucket::full(&limit, now))
+                    .take(&limit, now)
+            }
+            Request::Code => self
+                .codes
+                .entry(user_id)
+                .or_insert_with(|| TokenBucket::full(&self.code_limit, now))
+                .take(&self.code_limit, now),
+        };
+
+        match taken {
+            Ok(()) => {
+                self.strikes.remove(&user_id);
+                Verdict::Allowed
+            }
+            Err(retry_after) => {
+                let strikes = self.strikes.entry(user_id).or_default();
+                *strikes += 1;
+                if *strikes < MAX_STRIKES {
+                    return Verdict::Throttled(retry_after);
+                }
+
+                self.strikes.remove(&user_id);
+                self.blocked_until.insert(user_id, now + BLOCK_DURATION);
+                Verdict::Blocked
+            }
+        }
+    }
+}
+
+/// Whether the request may go on. Tells throttled users when to try again
+/// and reports newly blocked users to the admins. Admins are never limited.
+pub(crate) async fn allow(
+    bot: &Bot,
+    msg: &Message,
+    rate_limits: &RateLimits,
+    request: Request,
+) -> HandlerResult<bool> {
+    let Some(user) = msg.from() else {
+        return Ok(true);
+    };
+    if admin_commands::is_admin(user.id) {
+        return Ok(true);
+    }
+
+    let verdict = rate_limits
+        .lock()
+        .await
+        .check(user.id, &request, Instant::now());
+
+    match verdict {
+        Verdict::Allowed => Ok(true),
+        Verdict::Throttled(retry_after) => {
+            let what = match request {
+                Request::Answer { .. } => "answers",
+                Request::Code => "riddle codes",
+            };
+            send_message(
+                bot,
+                msg.chat.id,
+                format!(
+                    "Sorry, you are sending {} too quickly. Please wait {} second(s) and try again.",
+                    what,
+                    retry_after.as_secs() + 1
+                ),
+            )
+            .await?;
+            Ok(false)
+        }
+        Verdict::Blocked => {
+            let minutes = BLOCK_DURATION.as_secs() / 60;
+            send_message(
+                bot,
+                msg.chat.id,
+                format!(
+                    "You kept sending requests too quickly, so you are blocked for {} minutes.",
+                    minutes
+                ),
+            )
+            .await?;
+
+            let what = match &request {
+                Request::Answer { riddle, .. } => format!("answers to riddle `{}`", riddle),
+                Request::Code => "riddle codes".to_owned(),
+            };
+            let username = user
+                .username
+                .as_ref()
+                .map(|username| format!(" (@{})", username))
+                .unwrap_or_default();
+            let report = format!(
+                "User {}{} was blocked for {} minutes for sending {} too quickly",
+                user.id, username, minutes, what
+            );
+            for admin in admin_commands::admins_from_env() {
+                // admins who never started the bot can't be messaged
+                if let Err(e) = send_message(bot, ChatId::from(admin), &report).await {
+                    log::warn!("Cannot report a blocked user to admin {}: {}", admin, e);
+                }
+            }
+            Ok(false)
+        }
+        Verdict::StillBlocked => Ok(false),
+    }
+}
@@ -1,15 +1,20 @@
 use lazy_static::lazy_static;
 use regex::Regex;
-use teloxide::{requests::Requester, types::ChatId, Bot};
+use teloxide::{
+    net::Download,
+    requests::Requester,
+    types::{ChatId, Document},
+    Bot,
+};
 
-pub(crate) type Error = Box<dyn std::error::Error + Send + Sync>;
-pub(crate) type HandlerResult<R = ()> = Result<R, Error>;
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+pub type HandlerResult<R = ()> = Result<R, Error>;
 
 lazy_static! {
     static ref RE: Regex = Regex::new(r"([\[_\*\[\]\(\)~`>#\+-=\|{}\.!])").unwrap();
 }
 
-pub(crate) fn escape_chars<T>(text: T) -> String
+pub fn escape_chars<T>(text: T) -> String
 where
     T: Into<String>,
 {
@@ -35,7 +40,7 @@ where
                 // .replace("!", "\\!")
 }
 
-pub(crate) async fn send_message<T>(bot: &Bot, chat_id: ChatId, message: T) -> HandlerResult
+pub async fn send_message<T>(bot: &Bot, chat_id: ChatId, message: T) -> HandlerResult
 where
     T: Into<String>,
 {
@@ -44,3 +49,31 @@ where
         .await?;
     Ok(())
 }
+
+/// Formats a duration like `1h 02m 03s`, leaving out leading zero units.
+pub fn format_duration(duration: chrono::Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// Largest document we are willing to download, in bytes.
+const MAX_DOCUMENT_SIZE: u32 = 1024 * 1024;
+
+pub async fn download_document(bot: &Bot, document: &Document) -> HandlerResult<String> {
+    if document.file.size > MAX_DOCUMENT_SIZE {
+        return Err(format!("The file is too big (max {} bytes)", MAX_DOCUMENT_SIZE).into());
+    }
+
+    let file = bot.get_file(&document.file.id).await?;
+    let mut content = Vec::new();
+    bot.download_file(&file.path, &mut content).await?;
+
+    Ok(String::from_utf8(content)?)
+}